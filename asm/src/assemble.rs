@@ -0,0 +1,1376 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+
+use crate::diag::{Diagnostic, Diagnostics};
+
+use crate::object::{Object, Reloc, RelocKind, SourceLine, Symbol};
+use crate::parse::{BinOp, Instruction, Operand, Statement, Value};
+use crate::{ByteInWord, Condition, Direction, Opcode, RelativeTo, ShiftSource, Source, Target};
+
+/// An assembled program: every instruction at its final address, along with
+/// the labels defined along the way.
+pub struct Program {
+    pub items: Vec<Item>,
+    pub labels: Vec<Label>,
+    pub calls: Calls,
+    /// The address each section starts at.
+    pub bases: [u16; 3],
+    /// The byte used to fill gaps in the image.
+    pub fill: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Section {
+    Text,
+    Data,
+    Bss,
+}
+
+impl Section {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Section::Text => ".text",
+            Section::Data => ".data",
+            Section::Bss => ".bss",
+        }
+    }
+}
+
+/// Where the sections are placed in memory.  A section without a base of
+/// its own follows the one before it.
+#[derive(Debug, Clone, Copy)]
+pub struct Layout {
+    pub code: u16,
+    pub data: Option<u16>,
+    pub bss: Option<u16>,
+    /// The stack grows down from here; the CPU starts with SP at 0, so the
+    /// first push lands just below 0x10000.
+    pub stack_top: usize,
+    /// How much room below `stack_top` to keep free of the program.
+    pub stack_size: usize,
+    pub fill: u8,
+}
+
+impl Default for Layout {
+    fn default() -> Layout {
+        Layout { code: 0, data: None, bss: None, stack_top: 0x10000, stack_size: 0x100, fill: 0 }
+    }
+}
+
+impl Layout {
+    /// Parses a comma-separated description such as
+    /// `code=0,data=0x400,stack=0x10000,stack-size=0x80`.
+    pub fn parse(spec: &str) -> Result<Layout, String> {
+        let mut layout = Layout::default();
+        for part in spec.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+            let (key, value) = part.split_once('=').ok_or_else(|| format!("expected `key=value`, found `{part}`"))?;
+            let n = match value.trim().strip_prefix("0x") {
+                Some(hex) => usize::from_str_radix(hex, 16),
+                None => value.trim().parse(),
+            }
+            .ok()
+            .filter(|n| *n <= 0x10000)
+            .ok_or_else(|| format!("invalid address `{value}`"))?;
+            let addr = || u16::try_from(n).map_err(|_| format!("`{key}` must be below 0x10000"));
+            match key.trim() {
+                "code" | "text" => layout.code = addr()?,
+                "data" => layout.data = Some(addr()?),
+                "bss" => layout.bss = Some(addr()?),
+                "stack" => layout.stack_top = n,
+                "stack-size" => layout.stack_size = n,
+                "fill" => layout.fill = u8::try_from(n).map_err(|_| "`fill` must be a byte".to_string())?,
+                other => return Err(format!("unknown layout key `{other}`")),
+            }
+        }
+        if layout.stack_size > layout.stack_top {
+            return Err("the stack does not fit below its top".into());
+        }
+        Ok(layout)
+    }
+}
+
+/// How each `CALL` to an address was encoded.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Calls {
+    /// Two-byte `Call`s, each a byte shorter than `CallWord`.
+    pub compact: usize,
+    pub long: usize,
+}
+
+/// An instruction, or the bytes emitted by a data directive (`op` is then
+/// `None`).
+pub struct Item {
+    pub line: usize,
+    pub expanded: bool,
+    pub section: Section,
+    pub addr: u16,
+    pub op: Option<Opcode>,
+    pub bytes: Vec<u8>,
+}
+
+pub struct Label {
+    pub line: usize,
+    pub name: String,
+    pub section: Section,
+    pub addr: u16,
+}
+
+impl Program {
+    /// The memory image, starting at address 0 and ending at the last byte
+    /// of initialized data.  Gaps are filled with the layout's fill byte,
+    /// and `.bss` with zeros where it falls inside the image.
+    pub fn image(&self) -> Vec<u8> {
+        let len = self
+            .items
+            .iter()
+            .filter(|item| item.section != Section::Bss)
+            .map(|item| usize::from(item.addr) + item.bytes.len())
+            .max()
+            .unwrap_or(0);
+        let mut image = vec![self.fill; len];
+        for item in &self.items {
+            let addr = usize::from(item.addr).min(len);
+            let end = (addr + item.bytes.len()).min(len);
+            image[addr..end].copy_from_slice(&item.bytes[..end - addr]);
+        }
+        image
+    }
+}
+
+/// How many layout passes to make before deciding the program will never
+/// settle.
+const MAX_PASSES: usize = 32;
+
+pub fn assemble(stmts: &[Statement]) -> Result<Program, Diagnostics> {
+    assemble_with(stmts, &Layout::default())
+}
+
+/// Assembles a program placed by `layout`, reporting every error found.
+pub fn assemble_with(stmts: &[Statement], layout: &Layout) -> Result<Program, Diagnostics> {
+    let (last, bases) = settle(stmts, Some(layout))?;
+    let overlaps = check_overlaps(&last.items, layout);
+    if !overlaps.is_empty() {
+        return Err(Diagnostics(overlaps));
+    }
+    Ok(Program {
+        items: last.items,
+        labels: last.labels,
+        calls: last.calls,
+        bases: bases.map(|base| base as u16),
+        fill: layout.fill,
+    })
+}
+
+/// Assembles a relocatable object.  Every section starts at zero, symbols
+/// that are not defined are left for the linker, and each field holding an
+/// address gets a relocation.
+pub fn assemble_object(stmts: &[Statement]) -> Result<Object, Diagnostics> {
+    let (last, _) = settle(stmts, None)?;
+    let mut object = Object { aligns: last.aligns, relocs: last.relocs, ..Object::default() };
+    for item in &last.items {
+        let bytes = &mut object.sections[item.section as usize];
+        let addr = usize::from(item.addr);
+        if bytes.len() < addr + item.bytes.len() {
+            bytes.resize(addr + item.bytes.len(), 0);
+        }
+        bytes[addr..addr + item.bytes.len()].copy_from_slice(&item.bytes);
+        if !item.bytes.is_empty() {
+            object.lines.push(SourceLine {
+                section: item.section,
+                offset: item.addr,
+                size: item.bytes.len(),
+                line: item.line,
+                expanded: item.expanded,
+            });
+        }
+    }
+    for (section, end) in last.ends.iter().enumerate() {
+        object.sections[section].resize(*end, 0);
+    }
+    let errors = last
+        .globals
+        .iter()
+        .filter(|(_, name)| !last.labels.iter().any(|label| label.name == *name))
+        .map(|(line, name)| Diagnostic::error(*line, format!("global `{name}` is not a label in this file")))
+        .collect::<Vec<_>>();
+    if !errors.is_empty() {
+        return Err(Diagnostics(errors));
+    }
+    object.symbols = last
+        .labels
+        .iter()
+        .map(|label| Symbol {
+            name: label.name.clone(),
+            section: label.section,
+            offset: label.addr,
+            global: last.globals.iter().any(|(_, name)| *name == label.name),
+        })
+        .collect();
+    Ok(object)
+}
+
+/// Lays the program out repeatedly, giving each pass the symbols and
+/// section bases found by the one before, until nothing moves, then makes
+/// the final pass.  Pseudo-instructions never get shorter from one pass to
+/// the next, so the layout settles.  Without a layout the program is
+/// assembled as an object, with every section based at zero.  Errors are
+/// only reported from the final pass.
+fn settle(stmts: &[Statement], layout: Option<&Layout>) -> Result<(Pass, [usize; 3]), Diagnostics> {
+    let object = layout.is_none();
+    let mut symbols = HashMap::new();
+    let mut sizes = vec![0; stmts.len()];
+    let mut bases = [layout.map_or(0, |layout| usize::from(layout.code)); 3];
+    for _ in 0..MAX_PASSES {
+        let before = sizes.clone();
+        let trial = pass(stmts, &symbols, &mut sizes, bases, true, object);
+        let next = match layout {
+            Some(layout) => {
+                let data = layout.data.map_or(trial.ends[0].next_multiple_of(2), usize::from);
+                let bss = layout.bss.map_or(trial.ends[1].max(data).next_multiple_of(2), usize::from);
+                [usize::from(layout.code), data, bss]
+            }
+            None => [0; 3],
+        };
+        let settled = trial.symbols == symbols && sizes == before && next == bases;
+        symbols = trial.symbols;
+        bases = next;
+        if settled {
+            let mut last = pass(stmts, &symbols, &mut sizes, bases, false, object);
+            if !last.errors.is_empty() {
+                last.errors.sort_by_key(|diag| diag.line);
+                return Err(Diagnostics(last.errors));
+            }
+            return Ok((last, bases));
+        }
+    }
+    let line = stmts.last().map_or(0, |stmt| stmt.line);
+    Err(Diagnostic::error(line, format!("layout did not settle after {MAX_PASSES} passes")).into())
+}
+
+/// Reports every place two pieces of the program land on the same memory,
+/// and the first place the program runs into the stack.
+fn check_overlaps(items: &[Item], layout: &Layout) -> Vec<Diagnostic> {
+    let mut regions = items
+        .iter()
+        .filter(|item| !item.bytes.is_empty())
+        .map(|item| (usize::from(item.addr), usize::from(item.addr) + item.bytes.len(), item))
+        .collect::<Vec<_>>();
+    regions.sort_by_key(|(start, _, item)| (*start, item.line));
+
+    let mut errors = vec![];
+    for pair in regions.windows(2) {
+        let ((_, end, a), (start, _, b)) = (pair[0], pair[1]);
+        if start < end {
+            let message = format!("{} at {start:#06X} overlaps {}", b.section.name(), a.section.name());
+            errors.push(Diagnostic::error(b.line, message).note(format!("the {} is from line {}", a.section.name(), a.line)));
+        }
+    }
+
+    let stack = layout.stack_top - layout.stack_size..layout.stack_top;
+    if let Some((start, _, item)) = regions.iter().find(|(start, end, _)| *start < stack.end && stack.start < *end) {
+        let message = format!(
+            "{} at {:#06X} runs into the stack at {:#06X}..{:#06X}",
+            item.section.name(),
+            start.max(&stack.start),
+            stack.start,
+            stack.end
+        );
+        errors.push(Diagnostic::error(item.line, message).note("`--layout stack=...,stack-size=...` moves or shrinks the stack"));
+    }
+    errors.sort_by_key(|diag| diag.line);
+    errors
+}
+
+/// What a symbol's value is relative to.  Outside an object everything is
+/// placed, so every value is absolute.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Base {
+    Absolute,
+    Section(Section),
+    /// A symbol defined in another object.
+    External(String),
+}
+
+/// The value of an expression: `offset` from `base`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Term {
+    base: Base,
+    offset: i64,
+}
+
+impl Term {
+    fn absolute(offset: i64) -> Term {
+        Term { base: Base::Absolute, offset }
+    }
+}
+
+struct Pass {
+    symbols: HashMap<String, Term>,
+    /// Each symbol defined so far in this pass, with the line defining it.
+    defined: HashMap<String, usize>,
+    section: Section,
+    /// Where the next statement of each section goes.
+    pcs: [usize; 3],
+    /// The end of each section.
+    ends: [usize; 3],
+    /// The largest `.align` in each section.
+    aligns: [usize; 3],
+    /// Whether the last instruction was an `If`.
+    after_if: bool,
+    /// The line of an `.allow fault` that lets the next instruction fault.
+    allow_fault: Option<usize>,
+    /// The stack frame opened by the last `.frame`.
+    frame: Option<Frame>,
+    items: Vec<Item>,
+    labels: Vec<Label>,
+    calls: Calls,
+    /// The names given to `.global`, with the line of each.
+    globals: Vec<(usize, String)>,
+    relocs: Vec<Reloc>,
+    errors: Vec<Diagnostic>,
+}
+
+/// A stack frame opened by `.frame`, which names the arguments above the
+/// return address, while `.local` names words pushed since.  Following the
+/// pushes and pops from there, an access such as `[sp+n]` gets the offset
+/// of slot `n` at that point.
+struct Frame {
+    /// Each slot, by the bytes pushed when it was on top of the stack; the
+    /// arguments were there before any push, so theirs are negative.
+    slots: HashMap<String, i64>,
+    /// The bytes pushed since the frame opened, or why that is not known.
+    depth: Result<i64, String>,
+    /// The depth at each label branched to or passed so far.
+    labels: HashMap<String, i64>,
+}
+
+impl Frame {
+    fn new(args: Vec<String>) -> Frame {
+        let slots = args.into_iter().zip((2..).step_by(2)).map(|(name, offset)| (name, -offset)).collect();
+        Frame { slots, depth: Ok(0), labels: HashMap::new() }
+    }
+
+    /// The offset from SP of `name`, if it is a slot.
+    fn slot(&self, name: &str) -> Option<Result<i64, String>> {
+        let position = *self.slots.get(name)?;
+        Some(match &self.depth {
+            Ok(depth) if depth - position < 0 => Err(format!("stack slot `{name}` has already been popped")),
+            Ok(depth) => Ok(depth - position),
+            Err(why) => Err(format!("the stack depth is not known here, so neither is `{name}`: {why}")),
+        })
+    }
+
+    /// The depth at `label`, which is also that of every branch to it.
+    fn label(&mut self, label: &str) -> Result<(), String> {
+        match (&self.depth, self.labels.get(label)) {
+            (Ok(depth), Some(other)) if depth != other => {
+                Err(format!("`{label}` is reached with {depth} bytes pushed, but a branch to it has {other}"))
+            }
+            (Ok(depth), _) => {
+                self.labels.insert(label.to_string(), *depth);
+                Ok(())
+            }
+            (Err(_), Some(other)) => {
+                self.depth = Ok(*other);
+                Ok(())
+            }
+            (Err(_), None) => Ok(()),
+        }
+    }
+
+    /// Follows the depth through the instructions `inst` assembled to.
+    /// After an `If` only branches may be skipped without losing track.
+    fn step(&mut self, inst: &Instruction, ops: &[Opcode], after_if: bool, line: usize) -> Result<(), String> {
+        let Ok(mut depth) = self.depth else {
+            return Ok(());
+        };
+        // A branch to a label, however it is spelled and whether or not it
+        // was made long, hands its depth on to the label.
+        let branch = matches!(inst.operands.as_slice(), [Operand::Value(_)])
+            && ops.iter().any(|op| matches!(op, Opcode::Branch(_) | Opcode::BranchIndirect));
+        if branch {
+            if let [Operand::Value(Value::Label(target))] = inst.operands.as_slice() {
+                match self.labels.get(target) {
+                    Some(other) if *other != depth => {
+                        return Err(format!("branch to `{target}` with {depth} bytes pushed, but it is reached with {other}"));
+                    }
+                    _ => self.labels.insert(target.clone(), depth),
+                };
+            }
+            if !after_if {
+                self.depth = Err(format!("nothing falls through the `{}` on line {line}", inst.mnemonic));
+            }
+            return Ok(());
+        }
+        for op in ops {
+            let change = match op {
+                Opcode::Push => 2,
+                Opcode::Pop | Opcode::Drop => -2,
+                Opcode::Return | Opcode::Halt | Opcode::BranchIndirect if !after_if => {
+                    self.depth = Err(format!("nothing falls through the `{op}` on line {line}"));
+                    return Ok(());
+                }
+                _ => 0,
+            };
+            if change != 0 && after_if {
+                self.depth = Err(format!("the `IF` before line {line} may skip its `{op}`"));
+                return Ok(());
+            }
+            depth += change;
+        }
+        self.depth = Ok(depth);
+        Ok(())
+    }
+}
+
+/// Assembles every statement once, starting each section at its base in
+/// `bases`.  Labels not yet reached take their value from `prev`, the
+/// previous pass; in a `layout` pass any still unknown are treated as zero.
+/// In an `object` they are external instead.  `sizes` holds the size of
+/// each statement, which pseudo-instructions may grow but never shrink.
+///
+/// A statement in error is reported and skipped, keeping the room it took
+/// in the previous pass so that the statements after it stay put.
+fn pass(
+    stmts: &[Statement],
+    prev: &HashMap<String, Term>,
+    sizes: &mut [usize],
+    bases: [usize; 3],
+    layout: bool,
+    object: bool,
+) -> Pass {
+    let mut pass = Pass {
+        symbols: prev.clone(),
+        defined: HashMap::new(),
+        section: Section::Text,
+        pcs: bases,
+        ends: bases,
+        aligns: [1; 3],
+        after_if: false,
+        allow_fault: None,
+        frame: None,
+        items: vec![],
+        labels: vec![],
+        calls: Calls::default(),
+        globals: vec![],
+        relocs: vec![],
+        errors: vec![],
+    };
+    for (stmt, size) in stmts.iter().zip(sizes.iter_mut()) {
+        if let Err(diag) = pass.statement(stmt, size, layout, object) {
+            let section = pass.section as usize;
+            pass.pcs[section] += *size;
+            pass.ends[section] = pass.ends[section].max(pass.pcs[section]);
+            pass.after_if = false;
+            pass.allow_fault = None;
+            pass.errors.push(diag);
+        }
+    }
+    pass
+}
+
+impl Pass {
+    fn statement(&mut self, stmt: &Statement, size: &mut usize, layout: bool, object: bool) -> Result<(), Diagnostic> {
+        let err = |message| located(stmt, message, None);
+        let section = self.section;
+        let mut pc = self.pcs[section as usize];
+        if let Some(label) = &stmt.label {
+            if let Some(first) = self.defined.get(label) {
+                return Err(err(format!("label `{label}` is defined more than once"))
+                    .note(format!("`{label}` is first defined on line {first}")));
+            }
+            self.defined.insert(label.clone(), stmt.line);
+            let value = if object { Term { base: Base::Section(section), offset: pc as i64 } } else { Term::absolute(pc as i64) };
+            self.symbols.insert(label.clone(), value);
+            self.labels.push(Label { line: stmt.line, name: label.clone(), section, addr: pc as u16 });
+            if let Some(frame) = &mut self.frame {
+                frame.label(label).map_err(err)?;
+            }
+        }
+        let Some(inst) = &stmt.inst else {
+            return Ok(());
+        };
+
+        let ctx = Context {
+            symbols: &self.symbols,
+            defined: &self.defined,
+            section,
+            pc: pc as u16,
+            layout,
+            object,
+            min_size: *size,
+            after_if: self.after_if,
+            frame: self.frame.as_ref(),
+            stack: Cell::new(false),
+            relocs: RefCell::new(vec![]),
+            operand: Cell::new(None),
+            notes: RefCell::new(vec![]),
+        };
+        // Underline the operand at fault, if there is no doubt which.
+        let fail = |message: String| {
+            let operand = ctx.operand.get().or((inst.operands.len() == 1).then_some(0));
+            let mut diag = located(stmt, message, operand);
+            diag.notes.splice(0..0, ctx.notes.take());
+            diag
+        };
+        match inst.mnemonic.as_str() {
+            ".EQU" => {
+                let (name, value) = equ(inst).map_err(err)?;
+                ctx.operand.set(Some(1));
+                let term = ctx.term(value).map_err(fail)?;
+                if let Some(first) = self.defined.get(name) {
+                    return Err(err(format!("symbol `{name}` is defined more than once"))
+                        .note(format!("`{name}` is first defined on line {first}")));
+                }
+                self.defined.insert(name.to_string(), stmt.line);
+                self.symbols.insert(name.to_string(), term);
+                return Ok(());
+            }
+            ".GLOBAL" | ".GLOBL" => {
+                for (i, operand) in inst.operands.iter().enumerate() {
+                    match operand {
+                        Operand::Value(Value::Label(name)) => self.globals.push((stmt.line, name.clone())),
+                        _ => {
+                            let message = format!("expected `{} name, ...`", inst.mnemonic.to_lowercase());
+                            return Err(located(stmt, message, Some(i)));
+                        }
+                    }
+                }
+                return Ok(());
+            }
+            ".FRAME" => {
+                let args = inst
+                    .operands
+                    .iter()
+                    .enumerate()
+                    .map(|(i, operand)| match operand {
+                        Operand::Value(Value::Label(name)) => Ok(name.clone()),
+                        _ => Err(located(stmt, "expected `.frame arg, ...`".into(), Some(i))),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.frame = Some(Frame::new(args));
+                return Ok(());
+            }
+            ".LOCAL" => {
+                let [Operand::Value(Value::Label(name))] = inst.operands.as_slice() else {
+                    return Err(fail("expected `.local name`".into()));
+                };
+                let Some(frame) = &mut self.frame else {
+                    return Err(err("`.local` outside a `.frame`".into()));
+                };
+                match &frame.depth {
+                    Ok(depth) if *depth <= 0 => {
+                        return Err(fail("nothing has been pushed in this frame for `.local` to name".into())
+                            .note("`.local` names the word on top of the stack; arguments are named by `.frame`"));
+                    }
+                    Ok(depth) => frame.slots.insert(name.clone(), *depth),
+                    Err(why) => return Err(fail(format!("the stack depth is not known here: {why}"))),
+                };
+                return Ok(());
+            }
+            ".ALLOW" => {
+                match inst.operands.as_slice() {
+                    [Operand::Value(Value::Label(name))] if name.eq_ignore_ascii_case("fault") => {
+                        self.allow_fault = Some(stmt.line);
+                    }
+                    _ => return Err(fail("expected `.allow fault`".into())),
+                }
+                return Ok(());
+            }
+            ".TEXT" | ".DATA" | ".BSS" if inst.operands.is_empty() => {
+                self.section = match inst.mnemonic.as_str() {
+                    ".TEXT" => Section::Text,
+                    ".DATA" => Section::Data,
+                    _ => Section::Bss,
+                };
+                self.after_if = false;
+                return Ok(());
+            }
+            ".ORG" if object => return Err(err("`.org` cannot be used in an object; the linker places sections".into())),
+            ".ORG" => {
+                let [addr] = inst.operands.as_slice() else {
+                    return Err(err("expected `.org address`".into()));
+                };
+                let addr = ctx.count(addr).map_err(fail)?;
+                if addr > 0xFFFF {
+                    return Err(fail(format!("`.org` address {addr:#X} is past the end of memory at 0xFFFF")));
+                }
+                self.pcs[section as usize] = addr;
+                self.ends[section as usize] = self.ends[section as usize].max(addr);
+                self.after_if = false;
+                return Ok(());
+            }
+            _ => {}
+        }
+
+        let emitted = ctx.emit(inst).map_err(fail)?;
+        if let (".ALIGN", [align, ..]) = (inst.mnemonic.as_str(), inst.operands.as_slice()) {
+            let align = ctx.count(align).map_err(fail)?;
+            self.aligns[section as usize] = self.aligns[section as usize].max(align);
+        }
+        let relocs = ctx.relocs.take();
+        let reserves = matches!(&emitted, Emitted::Data(bytes) if bytes.iter().all(|b| *b == 0));
+        if section == Section::Bss && (!reserves || !relocs.is_empty()) {
+            return Err(err("`.bss` may only reserve space".into()));
+        }
+        for (at, kind, term) in relocs {
+            let symbol = match term.base {
+                Base::Section(section) => section.name().to_string(),
+                Base::External(name) => name,
+                Base::Absolute => continue,
+            };
+            self.relocs.push(Reloc { section, offset: (pc + at) as u16, kind, symbol, addend: term.offset });
+        }
+
+        let ops = match &emitted {
+            Emitted::Op(op) => std::slice::from_ref(op),
+            Emitted::Pseudo(ops) => ops.as_slice(),
+            Emitted::Data(_) => &[],
+        };
+        if let (Some(op), None) = (ops.iter().find(|op| op.faults()), self.allow_fault) {
+            return Err(fail(format!("`{op}` sends the CPU to its fault state"))
+                .note("`STORE` needs a memory operand such as `[dp+0x10]`")
+                .note("put `.allow fault` on the line before to emit it anyway"));
+        }
+        self.allow_fault = None;
+        if let Some(frame) = &mut self.frame {
+            frame.step(inst, ops, self.after_if, stmt.line).map_err(fail)?;
+        }
+
+        let start = pc;
+        let item = |addr: usize, op, bytes| Item { line: stmt.line, expanded: stmt.expanded, section, addr: addr as u16, op, bytes };
+        let mut items = vec![];
+        match emitted {
+            Emitted::Data(bytes) => {
+                pc += bytes.len();
+                items.push(item(start, None, bytes));
+            }
+            Emitted::Op(op) => {
+                let bytes = op.encode().map_err(|e| fail(e.to_string()))?.bytes();
+                pc += bytes.len();
+                items.push(item(start, Some(op), bytes));
+            }
+            Emitted::Pseudo(ops) => {
+                for op in ops {
+                    let bytes = op.encode().map_err(|e| fail(e.to_string()))?.bytes();
+                    let len = bytes.len();
+                    items.push(Item { expanded: true, ..item(pc, Some(op), bytes) });
+                    pc += len;
+                }
+            }
+        }
+        if inst.mnemonic == "CALL" {
+            match items[0].op {
+                Some(Opcode::Call(_)) => self.calls.compact += 1,
+                Some(Opcode::CallWord(_)) => self.calls.long += 1,
+                _ => {}
+            }
+        }
+        self.after_if = matches!(items.last(), Some(Item { op: Some(Opcode::If(_)), .. }));
+        self.items.extend(items);
+        *size = pc - start;
+        self.pcs[section as usize] = pc;
+        self.ends[section as usize] = self.ends[section as usize].max(pc);
+        if pc > 0x10000 {
+            self.errors.push(err(format!("{} does not fit in memory", section.name())));
+        }
+        Ok(())
+    }
+}
+
+/// An error on `stmt`, underlining `operand`, or else the mnemonic.  Code
+/// expanded from a macro has no columns in the source line.
+fn located(stmt: &Statement, message: String, operand: Option<usize>) -> Diagnostic {
+    let diag = Diagnostic::error(stmt.line, message);
+    if stmt.expanded {
+        return match &stmt.inst {
+            Some(inst) => diag.note(format!("in the `{}` expanded from this line", inst.mnemonic)),
+            None => diag.note("in code expanded from this line"),
+        };
+    }
+    let span = stmt.inst.as_ref().and_then(|inst| match operand {
+        Some(i) => inst.spans.get(i).cloned(),
+        None => Some(inst.span.clone()),
+    });
+    diag.at(span)
+}
+
+/// What a single statement assembles to.
+enum Emitted {
+    Data(Vec<u8>),
+    Op(Opcode),
+    /// The instructions a pseudo-instruction expanded to.
+    Pseudo(Vec<Opcode>),
+}
+
+struct Context<'a> {
+    symbols: &'a HashMap<String, Term>,
+    /// Symbols defined earlier in this pass.
+    defined: &'a HashMap<String, usize>,
+    section: Section,
+    pc: u16,
+    layout: bool,
+    object: bool,
+    /// The size this statement had in the previous pass.
+    min_size: usize,
+    /// Whether the statement follows an `If`, which may skip it.
+    after_if: bool,
+    frame: Option<&'a Frame>,
+    /// Whether an SP-relative offset is being evaluated, where the names
+    /// of stack slots may be used.
+    stack: Cell<bool>,
+    /// The fields of this statement left for the linker, by offset from
+    /// its start.
+    relocs: RefCell<Vec<(usize, RelocKind, Term)>>,
+    /// The operand being evaluated, where there is more than one.
+    operand: Cell<Option<usize>>,
+    /// Notes to go with an error.
+    notes: RefCell<Vec<String>>,
+}
+
+impl Context<'_> {
+    /// Evaluates an expression that must be a plain number.
+    fn value(&self, v: &Value) -> Result<i64, String> {
+        let term = self.term(v)?;
+        match term.base {
+            Base::Absolute => Ok(term.offset),
+            Base::Section(section) => Err(format!("addresses in {} are not known until link time", section.name())),
+            Base::External(name) => Err(format!("`{name}` is not known until link time")),
+        }
+    }
+
+    /// Evaluates an expression that may be an address the linker has yet
+    /// to place, plus or minus a constant.
+    fn term(&self, v: &Value) -> Result<Term, String> {
+        let (base, n) = match v {
+            Value::Number(n) => (Base::Absolute, Some(*n)),
+            Value::Label(name) if self.stack.get() && self.frame.and_then(|frame| frame.slot(name)).is_some() => {
+                let offset = self.frame.and_then(|frame| frame.slot(name)).unwrap()?;
+                return Ok(Term::absolute(offset));
+            }
+            Value::Label(name) => match self.symbols.get(name) {
+                Some(term) => return Ok(term.clone()),
+                None if self.object => return Ok(Term { base: Base::External(name.clone()), offset: 0 }),
+                None if self.layout => (Base::Absolute, Some(0)),
+                None => {
+                    if self.frame.is_some_and(|frame| frame.slots.contains_key(name)) {
+                        self.notes.borrow_mut().push(format!("`{name}` is a stack slot, which is only known in `[sp+{name}]`"));
+                    } else if let Some(near) = self.closest(name) {
+                        self.notes.borrow_mut().push(format!("did you mean `{near}`?"));
+                    }
+                    return Err(format!("undefined symbol `{name}`"));
+                }
+            },
+            Value::Binary(BinOp::Add, l, r) => {
+                let (l, r) = (self.term(l)?, self.term(r)?);
+                let base = match (l.base, r.base) {
+                    (base, Base::Absolute) | (Base::Absolute, base) => base,
+                    _ => return Err("cannot add two addresses".into()),
+                };
+                (base, l.offset.checked_add(r.offset))
+            }
+            Value::Binary(BinOp::Sub, l, r) => {
+                let (l, r) = (self.term(l)?, self.term(r)?);
+                let base = match (l.base, r.base) {
+                    (base, Base::Absolute) => base,
+                    (l, r) if l == r => Base::Absolute,
+                    _ => return Err("cannot subtract addresses that the linker places apart".into()),
+                };
+                (base, l.offset.checked_sub(r.offset))
+            }
+            Value::Neg(v) => (Base::Absolute, self.value(v)?.checked_neg()),
+            Value::Hi(v) => (Base::Absolute, Some(i64::from(address(self.value(v)?)? >> 8))),
+            Value::Lo(v) => (Base::Absolute, Some(i64::from(address(self.value(v)?)? & 0xFF))),
+            Value::Binary(op, l, r) => {
+                let (l, r) = (self.value(l)?, self.value(r)?);
+                let n = match op {
+                    BinOp::Add => l.checked_add(r),
+                    BinOp::Sub => l.checked_sub(r),
+                    BinOp::Mul => l.checked_mul(r),
+                    BinOp::Div if r == 0 && self.layout => Some(0),
+                    BinOp::Div if r == 0 => return Err("division by zero".into()),
+                    BinOp::Div => l.checked_div(r),
+                    BinOp::And => Some(l & r),
+                    BinOp::Or => Some(l | r),
+                    BinOp::Shl => u32::try_from(r).ok().and_then(|r| l.checked_shl(r)),
+                    BinOp::Shr => u32::try_from(r).ok().and_then(|r| l.checked_shr(r)),
+                };
+                (Base::Absolute, n)
+            }
+        };
+        let offset = n.ok_or("arithmetic overflow in expression")?;
+        Ok(Term { base, offset })
+    }
+
+    /// The defined symbol most like `name`, to suggest in its place.
+    fn closest(&self, name: &str) -> Option<&str> {
+        self.symbols
+            .keys()
+            .map(|symbol| (distance(&symbol.to_ascii_lowercase(), &name.to_ascii_lowercase()), symbol))
+            .filter(|(d, symbol)| *d <= 2 && *d * 3 <= symbol.len() + 1)
+            .min()
+            .map(|(_, symbol)| symbol.as_str())
+    }
+
+    /// What a label in the current section is relative to.
+    fn here(&self) -> Base {
+        if self.object {
+            Base::Section(self.section)
+        } else {
+            Base::Absolute
+        }
+    }
+
+    /// Evaluates a field of the statement `at` bytes from its start.  An
+    /// address the linker has yet to place is left for it to fill in, and
+    /// reads as zero until then.
+    fn relocate(&self, v: &Value, kind: RelocKind, at: usize) -> Result<i64, String> {
+        let term = self.term(v)?;
+        if term.base == Base::Absolute {
+            return Ok(term.offset);
+        }
+        self.relocs.borrow_mut().push((at, kind, term));
+        Ok(0)
+    }
+
+    fn resolved(&self, v: &Value) -> bool {
+        v.symbols().iter().all(|name| self.symbols.contains_key(*name))
+    }
+
+    fn emit(&self, inst: &Instruction) -> Result<Emitted, String> {
+        if let Some(bytes) = self.directive(inst)? {
+            return Ok(Emitted::Data(bytes));
+        }
+        if let Some(ops) = self.pseudo(inst)? {
+            return Ok(Emitted::Pseudo(ops));
+        }
+        Ok(Emitted::Op(self.opcode(inst)?))
+    }
+
+    /// A size or count must not depend on the layout, so it may only refer
+    /// to symbols defined before it.
+    fn count(&self, operand: &Operand) -> Result<usize, String> {
+        match operand {
+            Operand::Value(v) if v.symbols().iter().all(|name| self.defined.contains_key(*name)) => {
+                let n = self.value(v)?;
+                usize::try_from(n).ok().filter(|n| *n <= 0x10000).ok_or_else(|| format!("invalid size {n}"))
+            }
+            Operand::Value(_) => Err("size must not refer to a label defined later".into()),
+            _ => Err("expected a size".into()),
+        }
+    }
+
+    fn byte(&self, operand: &Operand) -> Result<u8, String> {
+        match operand {
+            Operand::Value(v) => {
+                let n = self.value(v)?;
+                if (-0x80..=0xFF).contains(&n) {
+                    Ok(n as u8)
+                } else {
+                    Err(format!("{n} is not a byte"))
+                }
+            }
+            _ => Err("expected a byte value".into()),
+        }
+    }
+
+    fn directive(&self, inst: &Instruction) -> Result<Option<Vec<u8>>, String> {
+        let operands = inst.operands.as_slice();
+        let fill = |rest: &[Operand]| match rest {
+            [] => Ok(0),
+            [fill] => {
+                self.operand.set(Some(1));
+                self.byte(fill)
+            }
+            _ => Err(format!("`{}` takes a size and an optional fill byte", inst.mnemonic)),
+        };
+
+        let bytes = match inst.mnemonic.as_str() {
+            ".BYTE" | ".WORD" | ".ASCII" if operands.is_empty() => {
+                return Err(format!("`{}` requires an operand", inst.mnemonic))
+            }
+            ".BYTE" => {
+                let mut bytes = vec![];
+                for (i, operand) in operands.iter().enumerate() {
+                    self.operand.set(Some(i));
+                    bytes.push(self.byte(operand)?);
+                }
+                bytes
+            }
+            ".WORD" => {
+                let mut bytes = vec![];
+                for (i, operand) in operands.iter().enumerate() {
+                    self.operand.set(Some(i));
+                    let n = match operand {
+                        Operand::Value(v) => self.relocate(v, RelocKind::Word, bytes.len())?,
+                        _ => return Err("expected a word value".into()),
+                    };
+                    bytes.extend(word(n)?.to_be_bytes());
+                }
+                bytes
+            }
+            ".ASCII" => {
+                let mut bytes = vec![];
+                for (i, operand) in operands.iter().enumerate() {
+                    self.operand.set(Some(i));
+                    match operand {
+                        Operand::Str(s) => bytes.extend(s),
+                        _ => return Err("expected a string".into()),
+                    }
+                }
+                bytes
+            }
+            ".SPACE" => match operands {
+                [size, rest @ ..] => {
+                    self.operand.set(Some(0));
+                    let size = self.count(size)?;
+                    vec![fill(rest)?; size]
+                }
+                [] => return Err("`.SPACE` requires a size".into()),
+            },
+            ".ALIGN" => match operands {
+                [align, rest @ ..] => {
+                    self.operand.set(Some(0));
+                    let align = self.count(align)?;
+                    if align == 0 {
+                        return Err("alignment must be at least 1".into());
+                    }
+                    let pc = usize::from(self.pc);
+                    vec![fill(rest)?; pc.next_multiple_of(align) - pc]
+                }
+                [] => return Err("`.ALIGN` requires an alignment".into()),
+            },
+            _ => return Ok(None),
+        };
+        Ok(Some(bytes))
+    }
+
+    fn pseudo(&self, inst: &Instruction) -> Result<Option<Vec<Opcode>>, String> {
+        let ops = match (inst.mnemonic.as_str(), inst.operands.as_slice()) {
+            ("LI", [Operand::Value(v) | Operand::Imm(v)]) => vec![self.load_immediate(v)?],
+            ("LI", _) => return Err("expected `LI value`".into()),
+            ("BR" | "BRANCH", [Operand::Value(v)]) if !v.symbols().is_empty() => match self.long_branch(v)? {
+                Some(ops) => ops,
+                None => return Ok(None),
+            },
+            _ => return Ok(None),
+        };
+        Ok(Some(ops))
+    }
+
+    /// The shortest load of a 16-bit constant.  Each choice leaves the flags
+    /// alone, as a plain `Load` does; building the value with `Or` would be
+    /// longer than `LoadImmediateWord` and clobber Z, N and C besides.
+    fn load_immediate(&self, v: &Value) -> Result<Opcode, String> {
+        if self.term(v)?.base != Base::Absolute {
+            return Ok(Opcode::LoadImmediateWord(word(self.relocate(v, RelocKind::Word, 1)?)?));
+        }
+        let w = word(self.value(v)?)?;
+        let [hi, lo] = w.to_be_bytes();
+        let short = self.min_size <= 2;
+        Ok(if short && hi == 0 {
+            Opcode::Load(Source::Const(ByteInWord::Lo, lo))
+        } else if short && lo == 0 {
+            Opcode::Load(Source::Const(ByteInWord::Hi, hi))
+        } else {
+            Opcode::LoadImmediateWord(w)
+        })
+    }
+
+    /// Rewrites a branch whose target is out of reach of `Branch` into one
+    /// through the accumulator, or returns `None` if the short form will do.
+    ///
+    /// After an `If` the whole sequence must be skipped as one instruction,
+    /// and falling through must leave E set as a skipped `Branch` would:
+    ///
+    /// ```text
+    ///     BR +2          ; condition held: take the branch
+    ///     BR +4          ; it did not: go on to `IF E`
+    ///     LOADW #delta
+    ///     BR a
+    ///     IF E           ; E is clear here, so the NOP is skipped...
+    ///     NOP            ; ...and E is set again after it
+    /// ```
+    ///
+    /// Either way, a long branch taken clobbers the accumulator.  A branch
+    /// the linker resolves is always short.
+    fn long_branch(&self, v: &Value) -> Result<Option<Vec<Opcode>>, String> {
+        let target = self.term(v)?;
+        if target.base != self.here() {
+            return Ok(None);
+        }
+        let target = target.offset;
+        let pc = i64::from(self.pc);
+        let short = target - (pc + 2);
+        if self.min_size <= 2 && (!self.resolved(v) || (-0x400..=0x3FF).contains(&short)) {
+            return Ok(None);
+        }
+        let ops = if self.after_if {
+            let delta = (target - (pc + 8)) as u16;
+            vec![
+                Opcode::Branch(Target::I11(2)),
+                Opcode::Branch(Target::I11(4)),
+                Opcode::LoadImmediateWord(delta),
+                Opcode::BranchIndirect,
+                Opcode::If(Condition::Else),
+                Opcode::Nop,
+            ]
+        } else {
+            let delta = (target - (pc + 4)) as u16;
+            vec![Opcode::LoadImmediateWord(delta), Opcode::BranchIndirect]
+        };
+        Ok(Some(ops))
+    }
+
+    fn opcode(&self, inst: &Instruction) -> Result<Opcode, String> {
+        let mnemonic = inst.mnemonic.as_str();
+        let operand = match inst.operands.as_slice() {
+            [] => None,
+            [operand] => Some(operand),
+            _ => return Err(format!("`{mnemonic}` takes at most one operand")),
+        };
+
+        let simple = match mnemonic {
+            "NOP" => Some(Opcode::Nop),
+            "HALT" => Some(Opcode::Halt),
+            "TRAP" => Some(Opcode::Trap),
+            "DROP" => Some(Opcode::Drop),
+            "PUSH" => Some(Opcode::Push),
+            "POP" => Some(Opcode::Pop),
+            "RET" | "RETURN" => Some(Opcode::Return),
+            "NOT" => Some(Opcode::Not),
+            "OUT" | "OUTLO" => Some(Opcode::OutLo),
+            "OUTHI" => Some(Opcode::OutHi),
+            "SETDP" => Some(Opcode::SetDataPointer),
+            "TEST" => Some(Opcode::Test),
+            "STATUS" => Some(Opcode::Status),
+            _ => None,
+        };
+
+        if let Some(op) = simple {
+            return match operand {
+                None => Ok(op),
+                Some(_) => Err(format!("`{mnemonic}` takes no operand")),
+            };
+        }
+
+        let Some(operand) = operand else {
+            let known = matches!(
+                mnemonic,
+                "LOAD" | "STORE" | "ADD" | "SUB" | "AND" | "OR" | "XOR" | "SHL" | "SHR" | "BR" | "BRANCH" | "CALL" | "CALLW" | "LOADW" | "IF"
+            );
+            return Err(if known {
+                format!("`{mnemonic}` requires an operand")
+            } else {
+                format!("unknown instruction `{mnemonic}`")
+            });
+        };
+
+        let op = match mnemonic {
+            "LOAD" => match operand {
+                Operand::RamAccum(r, m) => Opcode::LoadIndirect(*r, *m),
+                operand => Opcode::Load(self.source(operand)?),
+            },
+            "STORE" => Opcode::Store(self.source(operand)?),
+            "ADD" => Opcode::Add(self.source(operand)?),
+            "SUB" => Opcode::Sub(self.source(operand)?),
+            "AND" => Opcode::And(self.source(operand)?),
+            "OR" => Opcode::Or(self.source(operand)?),
+            "XOR" => Opcode::Xor(self.source(operand)?),
+            "SHL" => Opcode::Shift(Direction::Left, self.shift_source(operand)?),
+            "SHR" => Opcode::Shift(Direction::Right, self.shift_source(operand)?),
+            "BR" | "BRANCH" => match operand {
+                Operand::Accum => Opcode::BranchIndirect,
+                Operand::Value(v) => {
+                    // A plain number is an offset; anything naming a symbol
+                    // is an address to branch to.
+                    let target = self.term(v)?;
+                    let offset = if v.symbols().is_empty() {
+                        self.value(v)?
+                    } else if target.base == Base::Absolute && self.object {
+                        return Err("a branch in an object cannot reach a fixed address".into());
+                    } else if target.base != self.here() {
+                        self.relocate(v, RelocKind::Branch, 0)?
+                    } else if !self.resolved(v) {
+                        0
+                    } else {
+                        let size = Opcode::Branch(Target::I11(0)).encode().map_or(2, |e| e.len());
+                        let next = self.pc as usize + size;
+                        target.offset - next as i64
+                    };
+                    if !(-0x400..=0x3FF).contains(&offset) {
+                        self.notes
+                            .borrow_mut()
+                            .push("a `BR` to a label reaches any address; a plain number is an offset".into());
+                        return Err(format!("branch offset {offset} is outside the range -1024..=1023"));
+                    }
+                    Opcode::Branch(Target::I11(offset as i16))
+                }
+                _ => return Err("expected a branch target or `a`".into()),
+            },
+            "CALL" => match operand {
+                Operand::Accum => Opcode::CallIndirect,
+                Operand::Value(v) => {
+                    // The decoder sign-extends the eleven bit address, so
+                    // `Call` reaches the first and last KiB of memory.  In
+                    // an object the linker checks the reach instead.
+                    let addr = word(self.relocate(v, RelocKind::Call, 0)?)?;
+                    if self.term(v)?.base != Base::Absolute {
+                        Opcode::Call(Target::U11(0))
+                    } else if let Some(target) = Target::call(addr).filter(|_| self.min_size <= 2) {
+                        Opcode::Call(target)
+                    } else {
+                        Opcode::CallWord(addr)
+                    }
+                }
+                _ => return Err("expected a call target or `a`".into()),
+            },
+            "CALLW" => match operand {
+                Operand::Value(v) => Opcode::CallWord(word(self.relocate(v, RelocKind::Word, 1)?)?),
+                _ => return Err("expected a call target".into()),
+            },
+            "LOADW" => match operand {
+                Operand::Imm(v) => Opcode::LoadImmediateWord(word(self.relocate(v, RelocKind::Word, 1)?)?),
+                _ => return Err("expected an immediate word".into()),
+            },
+            "IF" => match operand {
+                Operand::Value(Value::Label(name)) => Opcode::If(condition(name)?),
+                _ => return Err("expected a condition".into()),
+            },
+            _ => return Err(format!("unknown instruction `{mnemonic}`")),
+        };
+
+        Ok(op)
+    }
+
+    fn source(&self, operand: &Operand) -> Result<Source, String> {
+        match operand {
+            // `#hi(x)` loads the high byte, so a `hi()` or `lo()` must be the
+            // whole operand: `#hi(x)+0` would load it into the low byte.
+            Operand::Imm(Value::Hi(v)) => {
+                Ok(Source::Const(ByteInWord::Hi, (address(self.relocate(v, RelocKind::Hi, 1)?)? >> 8) as u8))
+            }
+            Operand::Imm(Value::Lo(v)) => Ok(Source::Const(ByteInWord::Lo, address(self.relocate(v, RelocKind::Lo, 1)?)? as u8)),
+            Operand::Imm(v) if v.has_byte() => Err("`hi()` and `lo()` must be the whole operand of `#`".into()),
+            Operand::Imm(v) if self.term(v)?.base != Base::Absolute => {
+                Err("an address the linker places must be loaded with `LI`, or a byte at a time with `hi()` and `lo()`".into())
+            }
+            Operand::Imm(v) => {
+                let n = self.value(v)?;
+                if (0..=0xFF).contains(&n) {
+                    Ok(Source::Const(ByteInWord::Lo, n as u8))
+                } else if (0..=0xFFFF).contains(&n) && n & 0xFF == 0 {
+                    Ok(Source::Const(ByteInWord::Hi, (n >> 8) as u8))
+                } else {
+                    Err(format!("constant {n:#X} does not fit in a single byte"))
+                }
+            }
+            Operand::Input(b) => Ok(Source::Data(*b)),
+            Operand::Ram(r, m, v) => Ok(Source::Ram(*r, *m, self.ram_offset(*r, v)?)),
+            _ => Err("expected `#const`, `in` or a memory operand".into()),
+        }
+    }
+
+    fn shift_source(&self, operand: &Operand) -> Result<ShiftSource, String> {
+        match operand {
+            Operand::Imm(v) => {
+                let n = self.value(v)?;
+                Ok(ShiftSource::Const(
+                    u8::try_from(n).map_err(|_| format!("shift amount {n} is outside the range 0..=255"))?,
+                ))
+            }
+            Operand::Input(ByteInWord::Lo) => Ok(ShiftSource::Data),
+            Operand::Ram(r, m, v) => Ok(ShiftSource::Ram(*r, *m, self.ram_offset(*r, v)?)),
+            _ => Err("expected `#const`, `in` or a memory operand".into()),
+        }
+    }
+
+    /// The offset of a memory operand, which for SP may name stack slots.
+    fn ram_offset(&self, r: RelativeTo, v: &Value) -> Result<u8, String> {
+        self.stack.set(r == RelativeTo::StackPointer);
+        let offset = self.offset(v);
+        self.stack.set(false);
+        offset
+    }
+
+    fn offset(&self, v: &Value) -> Result<u8, String> {
+        let n = self.value(v)?;
+        u8::try_from(n).map_err(|_| format!("address offset {n:#X} is outside the range 0x00..=0xFF"))
+    }
+}
+
+fn condition(name: &str) -> Result<Condition, String> {
+    match name.to_ascii_uppercase().as_str() {
+        "Z" | "ZERO" => Ok(Condition::Zero),
+        "NZ" | "NOTZERO" => Ok(Condition::NotZero),
+        "E" | "ELSE" => Ok(Condition::Else),
+        "NE" | "NOTELSE" => Ok(Condition::NotElse),
+        "N" | "NEG" => Ok(Condition::Negative),
+        "NN" | "NOTNEG" => Ok(Condition::NotNegative),
+        "C" | "CARRY" => Ok(Condition::Carry),
+        "NC" | "NOTCARRY" => Ok(Condition::NotCarry),
+        _ => Err(format!("unknown condition `{name}`")),
+    }
+}
+
+/// Splits `.equ name, value` into its parts.
+fn equ(inst: &Instruction) -> Result<(&str, &Value), String> {
+    match inst.operands.as_slice() {
+        [Operand::Value(Value::Label(name)), Operand::Value(value)] => Ok((name, value)),
+        _ => Err("expected `.equ name, value`".into()),
+    }
+}
+
+/// The edit distance between two names.
+fn distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let next = (prev + usize::from(ca != *cb)).min(row[j] + 1).min(row[j + 1] + 1);
+            prev = row[j + 1];
+            row[j + 1] = next;
+        }
+    }
+    row[b.len()]
+}
+
+pub(crate) fn word(n: i64) -> Result<u16, String> {
+    if (-0x8000..=0xFFFF).contains(&n) {
+        Ok(n as u16)
+    } else {
+        Err(format!("{n} is not a 16-bit word"))
+    }
+}
+
+/// Checks the value `hi()` or `lo()` takes apart, which must be an address.
+fn address(n: i64) -> Result<u16, String> {
+    u16::try_from(n).map_err(|_| format!("{n} is outside the range 0..=0xFFFF of `hi()` and `lo()`"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::parse;
+    use crate::sim::{Machine, Step};
+
+    fn image(src: &str) -> Vec<u8> {
+        assemble(&parse(src).unwrap()).unwrap().image()
+    }
+
+    fn errors(src: &str) -> Vec<String> {
+        let diags = match parse(src) {
+            Ok(stmts) => assemble(&stmts).err().expect("an error"),
+            Err(diags) => diags,
+        };
+        diags.0.into_iter().map(|diag| diag.message).collect::<Vec<_>>()
+    }
+
+    #[test]
+    fn mnemonics() {
+        assert_eq!(image("LOAD #0x12\nADD [sp+0x02]\nRET\n"), [0x80, 0x12, 0x8E, 0x02, 0x06]);
+        assert_eq!(
+            errors("FOO\nFOO #1\nLOAD\nNOP #1\n"),
+            [
+                "unknown instruction `FOO`",
+                "unknown instruction `FOO`",
+                "`LOAD` requires an operand",
+                "`NOP` takes no operand",
+            ]
+        );
+    }
+
+    #[test]
+    fn expressions() {
+        let src = ".equ base, 0x10\nLOAD #base * 2 + (1 << 2)\nLOAD #hi(0x1234)\nOR #lo(end)\nend:\n.word end - base\n";
+        assert_eq!(image(src), [0x80, 0x24, 0x81, 0x12, 0xA8, 0x06, 0xFF, 0xF6]);
+        let messages = ["constant 0x123 does not fit in a single byte", "undefined symbol `y`"];
+        assert_eq!(errors("LOAD #0x123\n.equ x, y\n"), messages);
+    }
+
+    #[test]
+    fn hi_lo() {
+        assert_eq!(image("LOAD #hi(0x1234)\nLOAD #lo(0x1234)\n.byte hi(0x1234)+1\n"), [0x81, 0x12, 0x80, 0x34, 0x13]);
+        let whole = "`hi()` and `lo()` must be the whole operand of `#`";
+        assert_eq!(errors("LOAD #hi(0x1234)+0\nLOAD #lo(0x1234)|0\n"), [whole, whole]);
+        assert_eq!(
+            errors("LOAD #hi(0x12345)\nLOAD #lo(-1)\n.byte hi(0x10000)\n"),
+            [
+                "74565 is outside the range 0..=0xFFFF of `hi()` and `lo()`",
+                "-1 is outside the range 0..=0xFFFF of `hi()` and `lo()`",
+                "65536 is outside the range 0..=0xFFFF of `hi()` and `lo()`",
+            ]
+        );
+    }
+
+    #[test]
+    fn load_immediate() {
+        assert_eq!(image("LI 0x12\nLI 0x1200\nLI 0x1234\n"), [0x80, 0x12, 0x81, 0x12, 0x3F, 0x12, 0x34]);
+        // A forward label settles on the short form once it is known.
+        assert_eq!(image("LI end\nend:\n"), [0x80, 0x02]);
+    }
+
+    #[test]
+    fn layout() {
+        let layout = Layout::parse("data=4,fill=0xEE").unwrap();
+        let program = assemble_with(&parse(".data\n.byte 0x01\n.text\nNOP\n").unwrap(), &layout).unwrap();
+        assert_eq!(program.image(), [0x00, 0xEE, 0xEE, 0xEE, 0x01]);
+        assert_eq!(errors("NOP\nNOP\n.org 1\nHALT\n"), [".text at 0x0001 overlaps .text"]);
+        assert_eq!(errors(".org 0xFFF0\nNOP\n"), [".text at 0xFFF0 runs into the stack at 0xFF00..0x10000"]);
+        assert_eq!(errors(".org 0x10000\nNOP\n"), ["`.org` address 0x10000 is past the end of memory at 0xFFFF"]);
+    }
+
+    /// Runs `src` and returns the accumulator when it halts.
+    fn run(src: &str) -> u16 {
+        let mut machine = Machine::new(&image(src));
+        while machine.step() == Step::Continue {}
+        machine.accum
+    }
+
+    #[test]
+    fn long_branch() {
+        let far = |flags| format!("{flags}\nIF Z\nBR far\nSTATUS\nHALT\n.space 0x800\nfar:\nLOAD #0x02\nHALT\n");
+        let bytes = [0xF0, 0x00, 0xC0, 0x02, 0xC0, 0x04, 0x3F, 0x08, 0x05, 0x0C, 0xF0, 0x02, 0x00, 0x10];
+        assert_eq!(image(&far(""))[..14], bytes);
+        // Taken with Z set; otherwise the whole sequence is skipped and E is
+        // set for what follows, just as for a short branch.
+        assert_eq!(run(&far("TEST")), 2);
+        assert_eq!(run(&far("")), 0x20);
+        assert_eq!(run("IF Z\nBR near\nSTATUS\nHALT\nnear:\nHALT\n"), 0x20);
+        assert_eq!(errors("BR 0x400\n"), ["branch offset 1024 is outside the range -1024..=1023"]);
+    }
+
+    #[test]
+    fn calls() {
+        // Eleven sign-extended bits reach the first and last KiB.
+        assert_eq!(image("CALL 0xFFF0\nCALL 0x03F0\nCALL 0x0400\n"), [0xD7, 0xF0, 0xD3, 0xF0, 0x3E, 0x04, 0x00]);
+        // A forward label settles on the form its final address needs.
+        let program = assemble(&parse("CALL near\nCALL far\nnear:\n.space 0x400\nfar:\nRET\n").unwrap()).unwrap();
+        assert_eq!(program.image()[..5], [0xD0, 0x05, 0x3E, 0x04, 0x05]);
+        assert_eq!((program.calls.compact, program.calls.long), (1, 1));
+    }
+
+    #[test]
+    fn frames() {
+        let named = ".frame n\nLOAD [sp+n]\nPUSH\n.local x\nLOAD [sp+n]\nADD [sp+x]\nDROP\nRET\n";
+        let plain = "LOAD [sp+0x02]\nPUSH\nLOAD [sp+0x04]\nADD [sp+0x00]\nDROP\nRET\n";
+        assert_eq!(image(named), image(plain));
+        assert_eq!(errors(".frame n\nPUSH\n.local x\nDROP\nLOAD [sp+x]\nRET\n"), ["stack slot `x` has already been popped"]);
+
+        // A branch hands its depth on to its label, whichever way it is
+        // spelled and however far it goes.
+        let out = "`out` is reached with 0 bytes pushed, but a branch to it has 2";
+        for (br, space) in [("BR", ""), ("BRANCH", ""), ("BRANCH", ".space 0x800\n")] {
+            let src = format!(".frame n\nPUSH\nTEST\nIF Z\n{br} out\nDROP\n{space}out:\nLOAD [sp+n]\nRET\n");
+            assert_eq!(errors(&src), [out], "{br} {space:?}");
+            let src = format!(".frame n\n{br} out\n{space}out:\nLOAD [sp+n]\nRET\n");
+            assert_eq!(image(&src)[..2], image(&format!("{br} out\n{space}out:\nLOAD [sp+0x02]\nRET\n"))[..2]);
+        }
+        assert_eq!(
+            errors(".frame n\nPUSH\nBR a\nLOAD [sp+n]\n"),
+            ["the stack depth is not known here, so neither is `n`: nothing falls through the `BR a` on line 3"]
+        );
+    }
+}
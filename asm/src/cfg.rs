@@ -0,0 +1,262 @@
+//! Control-flow graphs recovered from memory images, written as Graphviz
+//! DOT:
+//!
+//! ```text
+//! asm cfg asm/fib_recursive.s asm/lib/fib.s | dot -Tsvg > fib.svg
+//! ```
+//!
+//! Only code reachable from the entry point by direct branches and calls
+//! is decoded, so data mixed in with the code is left alone.  A basic block
+//! ends at every `Branch`, `Call`, `CallWord`, `Return`, `Halt` and `Trap`,
+//! and at every `If`, whose guarded instruction is a block of its own.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Write};
+
+use crate::debug::Label;
+use crate::disasm::Line;
+use crate::{Opcode, Target};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeKind {
+    /// On to the next instruction, including after a call returns.
+    Fallthrough,
+    /// Past the instruction an `If` guards.
+    Skip,
+    /// A branch taken.
+    Taken,
+    /// Into a subroutine.
+    Call,
+}
+
+impl EdgeKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EdgeKind::Fallthrough => "fallthrough",
+            EdgeKind::Skip => "skip",
+            EdgeKind::Taken => "taken",
+            EdgeKind::Call => "call",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Edge {
+    pub kind: EdgeKind,
+    pub to: u16,
+}
+
+#[derive(Debug)]
+pub struct Block {
+    pub start: u16,
+    pub lines: Vec<Line>,
+    pub edges: Vec<Edge>,
+    /// Whether the block ends in a `BR a` or `CALL a`, whose target is only
+    /// known at run time.
+    pub unresolved: bool,
+}
+
+impl Block {
+    pub fn size(&self) -> usize {
+        self.lines.iter().map(|line| line.bytes.len()).sum()
+    }
+
+    /// A block with no instructions starts with bytes that do not decode.
+    pub fn invalid(&self) -> bool {
+        self.lines.is_empty()
+    }
+}
+
+#[derive(Debug)]
+pub struct Cfg {
+    /// Every block, in address order.
+    pub blocks: Vec<Block>,
+}
+
+impl Cfg {
+    /// Recovers the graph of the code in `image` reachable from `entry`.
+    pub fn recover(image: &[u8], entry: u16) -> Cfg {
+        let mut lines: BTreeMap<u16, Line> = BTreeMap::new();
+        let mut invalid = BTreeSet::new();
+        let mut leaders = BTreeSet::from([entry]);
+        let mut work = vec![entry];
+        while let Some(addr) = work.pop() {
+            if lines.contains_key(&addr) || invalid.contains(&addr) {
+                continue;
+            }
+            let Some(line) = decode(image, addr) else {
+                invalid.insert(addr);
+                continue;
+            };
+            let (edges, _) = successors(image, &line);
+            if ends_block(&line.op) {
+                leaders.extend(edges.iter().map(|edge| edge.to));
+            }
+            work.extend(edges.iter().map(|edge| edge.to));
+            lines.insert(addr, line);
+        }
+        leaders.extend(&invalid);
+
+        let mut blocks = vec![];
+        for &start in &leaders {
+            let mut block = Block { start, lines: vec![], edges: vec![], unresolved: false };
+            let mut addr = start;
+            while let Some(line) = lines.remove(&addr) {
+                let next = next(&line);
+                let (edges, unresolved) = successors(image, &line);
+                let last = ends_block(&line.op) || leaders.contains(&next);
+                block.lines.push(line);
+                if last {
+                    block.edges = edges;
+                    block.unresolved = unresolved;
+                    break;
+                }
+                addr = next;
+            }
+            blocks.push(block);
+        }
+        Cfg { blocks }
+    }
+
+    /// Writes the graph in DOT, naming blocks that start at a label.
+    pub fn write_dot(&self, out: &mut dyn Write, labels: &[Label]) -> io::Result<()> {
+        writeln!(out, "digraph cfg {{")?;
+        writeln!(out, "  node [shape=box, fontname=\"monospace\"];")?;
+        for block in &self.blocks {
+            let mut text = String::new();
+            if let Some(label) = labels.iter().find(|label| label.addr == block.start) {
+                text.push_str(&format!("{}:\\l", escape(&label.name)));
+            }
+            text.push_str(&format!("{:#06X}, {} bytes\\l", block.start, block.size()));
+            for line in &block.lines {
+                text.push_str(&format!("{}\\l", escape(&line.to_string())));
+            }
+            let style = if block.invalid() {
+                text.push_str("not an instruction\\l");
+                ", color=red"
+            } else if block.unresolved {
+                text.push_str("unresolved target\\l");
+                ", color=orange"
+            } else {
+                ""
+            };
+            writeln!(out, "  \"{:04X}\" [label=\"{text}\"{style}];", block.start)?;
+        }
+        for block in &self.blocks {
+            for edge in &block.edges {
+                let style = match edge.kind {
+                    EdgeKind::Fallthrough => "",
+                    EdgeKind::Skip => ", style=dashed",
+                    EdgeKind::Taken => ", color=blue",
+                    EdgeKind::Call => ", style=bold",
+                };
+                writeln!(out, "  \"{:04X}\" -> \"{:04X}\" [label=\"{}\"{style}];", block.start, edge.to, edge.kind.name())?;
+            }
+        }
+        writeln!(out, "}}")
+    }
+}
+
+/// The instruction at `addr`, if the bytes there are one.
+fn decode(image: &[u8], addr: u16) -> Option<Line> {
+    let rest = image.get(usize::from(addr)..)?;
+    let (op, bytes) = Opcode::decode_exact(rest)?;
+    Some(Line { addr: usize::from(addr), bytes, op })
+}
+
+fn next(line: &Line) -> u16 {
+    (line.addr + line.bytes.len()) as u16
+}
+
+fn ends_block(op: &Opcode) -> bool {
+    matches!(
+        op,
+        Opcode::Branch(_)
+            | Opcode::BranchIndirect
+            | Opcode::Call(_)
+            | Opcode::CallWord(_)
+            | Opcode::CallIndirect
+            | Opcode::Return
+            | Opcode::Halt
+            | Opcode::Trap
+            | Opcode::If(_)
+    )
+}
+
+/// Where control may go after `line`, and whether it may also go somewhere
+/// only known at run time.
+fn successors(image: &[u8], line: &Line) -> (Vec<Edge>, bool) {
+    let next = next(line);
+    let edge = |kind, to| Edge { kind, to };
+    match &line.op {
+        Opcode::If(_) => {
+            let mut edges = vec![edge(EdgeKind::Fallthrough, next)];
+            if let Some(guarded) = decode(image, next) {
+                edges.push(edge(EdgeKind::Skip, self::next(&guarded)));
+            }
+            (edges, false)
+        }
+        Opcode::Branch(Target::I11(offset)) => (vec![edge(EdgeKind::Taken, next.wrapping_add(*offset as u16))], false),
+        Opcode::Call(target @ Target::U11(_)) => {
+            (vec![edge(EdgeKind::Call, target.extended()), edge(EdgeKind::Fallthrough, next)], false)
+        }
+        Opcode::CallWord(addr) => (vec![edge(EdgeKind::Call, *addr), edge(EdgeKind::Fallthrough, next)], false),
+        Opcode::CallIndirect => (vec![edge(EdgeKind::Fallthrough, next)], true),
+        Opcode::BranchIndirect => (vec![], true),
+        Opcode::Return | Opcode::Halt => (vec![], false),
+        _ => (vec![edge(EdgeKind::Fallthrough, next)], false),
+    }
+}
+
+/// Escapes text for a double-quoted DOT string.
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble::{assemble_with, Layout};
+    use crate::parse::parse;
+
+    fn recover(src: &str, layout: &str) -> Cfg {
+        let program = assemble_with(&parse(src).unwrap(), &Layout::parse(layout).unwrap()).unwrap();
+        Cfg::recover(&program.image(), 0)
+    }
+
+    fn edges(cfg: &Cfg) -> Vec<(u16, EdgeKind, u16)> {
+        cfg.blocks.iter().flat_map(|block| block.edges.iter().map(|edge| (block.start, edge.kind, edge.to))).collect()
+    }
+
+    #[test]
+    fn blocks() {
+        let cfg = recover("TEST\nIF Z\nBR done\nCALL sub\ndone:\nHALT\nsub:\nRET\n", "");
+        let starts = cfg.blocks.iter().map(|block| (block.start, block.size())).collect::<Vec<_>>();
+        assert_eq!(starts, [(0, 3), (3, 2), (5, 2), (7, 1), (8, 1)]);
+        assert_eq!(
+            edges(&cfg),
+            [
+                (0, EdgeKind::Fallthrough, 3),
+                (0, EdgeKind::Skip, 5),
+                (3, EdgeKind::Taken, 7),
+                (5, EdgeKind::Call, 8),
+                (5, EdgeKind::Fallthrough, 7),
+            ]
+        );
+    }
+
+    #[test]
+    fn reset() {
+        // Reset runs the fill of `NOP`s before code placed above address 0.
+        let cfg = recover("HALT\n", "code=0x04");
+        assert_eq!(cfg.blocks.iter().map(|block| (block.start, block.lines.len())).collect::<Vec<_>>(), [(0, 5)]);
+    }
+
+    #[test]
+    fn unresolved() {
+        let cfg = recover("BR a\n.byte 0x11\n", "");
+        assert!(cfg.blocks[0].unresolved);
+        let cfg = recover("BR data\ndata:\n.byte 0x11\n", "");
+        assert!(cfg.blocks[1].invalid());
+    }
+}
@@ -0,0 +1,140 @@
+//! Symbol tables and address-to-source maps for testbenches, written as
+//! JSON so that cocotb can load them with the standard library:
+//!
+//! ```text
+//! {
+//!   "labels": [
+//!     {"name": "fib_fn", "section": ".text", "address": 8, "size": 30}
+//!   ],
+//!   "lines": [
+//!     {"address": 0, "size": 2, "file": "asm/fib_recursive.s", "line": 1, "expanded": false}
+//!   ]
+//! }
+//! ```
+//!
+//! Addresses and sizes are in bytes.  A label's size runs to the next label
+//! in its section, or to the end of the section.
+
+use std::io::{self, Write};
+
+use crate::assemble::{Program, Section};
+
+#[derive(Debug, Clone, Default)]
+pub struct DebugInfo {
+    pub labels: Vec<Label>,
+    pub lines: Vec<Line>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Label {
+    pub name: String,
+    pub section: Section,
+    pub addr: u16,
+    pub size: usize,
+}
+
+/// The bytes at `addr` came from `line` of `file`; `expanded` if they are
+/// part of a macro or pseudo-instruction's expansion.
+#[derive(Debug, Clone)]
+pub struct Line {
+    pub addr: u16,
+    pub size: usize,
+    pub file: String,
+    pub line: usize,
+    pub expanded: bool,
+}
+
+impl DebugInfo {
+    /// The labels and lines of a program assembled from `file`.
+    pub fn from_program(program: &Program, file: &str) -> DebugInfo {
+        let mut ends = program.bases.map(usize::from);
+        for item in &program.items {
+            let end = &mut ends[item.section as usize];
+            *end = (*end).max(usize::from(item.addr) + item.bytes.len());
+        }
+        let placed = program.labels.iter().map(|label| (label.section, label.addr)).collect::<Vec<_>>();
+        let labels = program
+            .labels
+            .iter()
+            .zip(sizes(&placed, ends))
+            .map(|(label, size)| Label { name: label.name.clone(), section: label.section, addr: label.addr, size })
+            .collect();
+        let mut lines = program
+            .items
+            .iter()
+            .filter(|item| !item.bytes.is_empty())
+            .map(|item| Line {
+                addr: item.addr,
+                size: item.bytes.len(),
+                file: file.to_string(),
+                line: item.line,
+                expanded: item.expanded,
+            })
+            .collect::<Vec<_>>();
+        lines.sort_by_key(|line| line.addr);
+        DebugInfo { labels, lines }
+    }
+
+    pub fn write_json(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "{{")?;
+        writeln!(out, "  \"labels\": [")?;
+        for (i, label) in self.labels.iter().enumerate() {
+            let comma = if i + 1 < self.labels.len() { "," } else { "" };
+            writeln!(
+                out,
+                "    {{\"name\": {}, \"section\": \"{}\", \"address\": {}, \"size\": {}}}{comma}",
+                string(&label.name),
+                label.section.name(),
+                label.addr,
+                label.size
+            )?;
+        }
+        writeln!(out, "  ],")?;
+        writeln!(out, "  \"lines\": [")?;
+        for (i, line) in self.lines.iter().enumerate() {
+            let comma = if i + 1 < self.lines.len() { "," } else { "" };
+            writeln!(
+                out,
+                "    {{\"address\": {}, \"size\": {}, \"file\": {}, \"line\": {}, \"expanded\": {}}}{comma}",
+                line.addr,
+                line.size,
+                string(&line.file),
+                line.line,
+                line.expanded
+            )?;
+        }
+        writeln!(out, "  ]")?;
+        writeln!(out, "}}")
+    }
+}
+
+/// The size of each label at `placed`, given where each section ends.
+pub fn sizes(placed: &[(Section, u16)], ends: [usize; 3]) -> Vec<usize> {
+    placed
+        .iter()
+        .map(|(section, addr)| {
+            let next = placed
+                .iter()
+                .filter(|(s, a)| s == section && a > addr)
+                .map(|(_, a)| usize::from(*a))
+                .min()
+                .unwrap_or(ends[*section as usize]);
+            next.saturating_sub(usize::from(*addr))
+        })
+        .collect()
+}
+
+/// A JSON string literal.
+fn string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if u32::from(c) < 0x20 => out.push_str(&format!("\\u{:04x}", u32::from(c))),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
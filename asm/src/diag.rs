@@ -0,0 +1,128 @@
+//! Diagnostics: problems found in a source file, each tied to a line and,
+//! where known, the columns of the operand at fault.  Rendered against the
+//! source they look like this:
+//!
+//! ```text
+//! error: branch offset 1200 is outside the range -1024..=1023
+//!   --> asm/ops.s:17:8
+//!    |
+//! 17 |     BR 1200
+//!    |        ^^^^
+//!    = note: a `BR` to a label reaches any address; a plain number is an offset
+//! ```
+
+use std::io::{self, Write};
+use std::ops::Range;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub line: usize,
+    /// The bytes of the line to underline, or `None` for the whole line.
+    pub span: Option<Range<usize>>,
+    pub message: String,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn error(line: usize, message: impl Into<String>) -> Diagnostic {
+        Diagnostic { severity: Severity::Error, line, span: None, message: message.into(), notes: vec![] }
+    }
+
+    pub fn warning(line: usize, message: impl Into<String>) -> Diagnostic {
+        Diagnostic { severity: Severity::Warning, ..Diagnostic::error(line, message) }
+    }
+
+    pub fn at(self, span: Option<Range<usize>>) -> Diagnostic {
+        Diagnostic { span, ..self }
+    }
+
+    pub fn note(mut self, note: impl Into<String>) -> Diagnostic {
+        self.notes.push(note.into());
+        self
+    }
+
+    /// Writes the diagnostic along with the line of `source` it refers to.
+    pub fn render(&self, out: &mut dyn Write, file: &str, source: &str) -> io::Result<()> {
+        writeln!(out, "{}: {}", self.severity.name(), self.message)?;
+        let text = source.lines().nth(self.line.wrapping_sub(1)).unwrap_or("").trim_end();
+        let span = match &self.span {
+            Some(span) if span.end <= text.len() && text.is_char_boundary(span.start) && text.is_char_boundary(span.end) => {
+                span.clone()
+            }
+            _ => {
+                let start = text.len() - text.trim_start().len();
+                start..text.len()
+            }
+        };
+        let column = text[..span.start].chars().count() + 1;
+        let gutter = " ".repeat(self.line.to_string().len());
+        writeln!(out, "{gutter}--> {file}:{}:{column}", self.line)?;
+        if !text.is_empty() {
+            // Keep tabs so the carets line up under the text above them.
+            let indent = text[..span.start].chars().map(|c| if c == '\t' { '\t' } else { ' ' }).collect::<String>();
+            let carets = "^".repeat(text[span].chars().count().max(1));
+            writeln!(out, "{gutter} |")?;
+            writeln!(out, "{} | {text}", self.line)?;
+            writeln!(out, "{gutter} | {indent}{carets}")?;
+        }
+        for note in &self.notes {
+            writeln!(out, "{gutter} = note: {note}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)?;
+        for note in &self.notes {
+            write!(f, " ({note})")?;
+        }
+        Ok(())
+    }
+}
+
+/// Every problem found in a file.
+#[derive(Debug)]
+pub struct Diagnostics(pub Vec<Diagnostic>);
+
+impl Diagnostics {
+    pub fn render(&self, out: &mut dyn Write, file: &str, source: &str) -> io::Result<()> {
+        for diag in &self.0 {
+            diag.render(out, file, source)?;
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<Diagnostic> for Diagnostics {
+    fn from(diag: Diagnostic) -> Diagnostics {
+        Diagnostics(vec![diag])
+    }
+}
+
+impl std::fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let lines = self.0.iter().map(ToString::to_string).collect::<Vec<_>>();
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+impl std::error::Error for Diagnostics {}
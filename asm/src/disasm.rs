@@ -0,0 +1,181 @@
+use std::fmt;
+
+use crate::{AddressingMode, ByteInWord, Condition, Direction, Opcode, RelativeTo, ShiftSource, Source, Target};
+
+#[derive(Debug)]
+pub struct Line {
+    pub addr: usize,
+    pub bytes: Vec<u8>,
+    pub op: Opcode,
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let raw = self.bytes.iter().map(|b| format!("{b:02X}")).collect::<Vec<_>>().join(" ");
+        write!(f, "{:04X}: {raw:<8}  {}", self.addr, self.op)?;
+        if let Opcode::Branch(Target::I11(offset)) = self.op {
+            let target = (self.addr as i64 + self.bytes.len() as i64 + i64::from(offset)) & 0xFFFF;
+            write!(f, " ; -> {target:#06X}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Decodes a memory image, stopping at the trailing zero padding.  Any byte
+/// that does not decode to an instruction which re-encodes identically is
+/// emitted as data.
+pub fn disassemble(bytes: &[u8]) -> Vec<Line> {
+    let end = bytes.iter().rposition(|b| *b != 0).map_or(0, |i| i + 1);
+
+    let mut lines = vec![];
+    let mut addr = 0;
+    while addr < end {
+        let rest = &bytes[addr..];
+        let (op, encoded) = Opcode::decode_exact(rest).unwrap_or((Opcode::Text(bytes[addr]), vec![bytes[addr]]));
+        let len = encoded.len();
+        lines.push(Line { addr, bytes: encoded, op });
+        addr += len;
+    }
+    lines
+}
+
+impl fmt::Display for Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Opcode::Text(b) => write!(f, ".byte {b:#04X}"),
+            Opcode::Nop => write!(f, "NOP"),
+            Opcode::Halt => write!(f, "HALT"),
+            Opcode::Trap => write!(f, "TRAP"),
+            Opcode::OutLo => write!(f, "OUTLO"),
+            Opcode::OutHi => write!(f, "OUTHI"),
+            Opcode::Push => write!(f, "PUSH"),
+            Opcode::Pop => write!(f, "POP"),
+            Opcode::Drop => write!(f, "DROP"),
+            Opcode::Return => write!(f, "RET"),
+            Opcode::BranchIndirect => write!(f, "BR a"),
+            Opcode::CallIndirect => write!(f, "CALL a"),
+            Opcode::Status => write!(f, "STATUS"),
+            Opcode::CallWord(w) => write!(f, "CALLW {w:#06X}"),
+            Opcode::LoadImmediateWord(w) => write!(f, "LOADW #{w:#06X}"),
+            Opcode::Not => write!(f, "NOT"),
+            Opcode::SetDataPointer => write!(f, "SETDP"),
+            Opcode::Test => write!(f, "TEST"),
+            Opcode::LoadIndirect(r, m) => write!(f, "LOAD {}", Memory(*r, *m, "a")),
+            Opcode::Load(s) => write!(f, "LOAD {s}"),
+            Opcode::Store(s) => write!(f, "STORE {s}"),
+            Opcode::Add(s) => write!(f, "ADD {s}"),
+            Opcode::Sub(s) => write!(f, "SUB {s}"),
+            Opcode::And(s) => write!(f, "AND {s}"),
+            Opcode::Or(s) => write!(f, "OR {s}"),
+            Opcode::Xor(s) => write!(f, "XOR {s}"),
+            Opcode::Shift(d, s) => {
+                let mnemonic = match d {
+                    Direction::Left => "SHL",
+                    Direction::Right => "SHR",
+                };
+                write!(f, "{mnemonic} {s}")
+            }
+            Opcode::Branch(Target::I11(v)) => write!(f, "BR {v}"),
+            Opcode::Branch(Target::U11(v)) => write!(f, "BR {v}"),
+            Opcode::Call(Target::I11(v)) => write!(f, "CALL {v}"),
+            Opcode::Call(target @ Target::U11(_)) => write!(f, "CALL {:#06X}", target.extended()),
+            Opcode::If(c) => write!(f, "IF {c}"),
+        }
+    }
+}
+
+struct Memory<'a>(RelativeTo, AddressingMode, &'a str);
+
+impl fmt::Display for Memory<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let base = match self.0 {
+            RelativeTo::DataPointer => "dp",
+            RelativeTo::StackPointer => "sp",
+        };
+        let inner = if self.2.is_empty() {
+            base.to_string()
+        } else {
+            format!("{base}+{}", self.2)
+        };
+        match self.1 {
+            AddressingMode::Direct => write!(f, "[{inner}]"),
+            AddressingMode::Indirect => write!(f, "[[{inner}]]"),
+        }
+    }
+}
+
+fn offset(a: u8) -> String {
+    if a == 0 {
+        String::new()
+    } else {
+        format!("{a:#04X}")
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Source::Const(ByteInWord::Lo, c) => write!(f, "#{c:#04X}"),
+            // Zero would load as the low byte, which encodes differently.
+            Source::Const(ByteInWord::Hi, 0) => write!(f, "#hi(0)"),
+            Source::Const(ByteInWord::Hi, c) => write!(f, "#{:#06X}", u16::from(*c) << 8),
+            Source::Data(ByteInWord::Lo) => write!(f, "in"),
+            Source::Data(ByteInWord::Hi) => write!(f, "in.hi"),
+            Source::Ram(r, m, a) => write!(f, "{}", Memory(*r, *m, &offset(*a))),
+        }
+    }
+}
+
+impl fmt::Display for ShiftSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ShiftSource::Const(c) => write!(f, "#{c}"),
+            ShiftSource::Data => write!(f, "in"),
+            ShiftSource::Ram(r, m, a) => write!(f, "{}", Memory(*r, *m, &offset(*a))),
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Condition::Zero => "Z",
+            Condition::NotZero => "NZ",
+            Condition::Else => "E",
+            Condition::NotElse => "NE",
+            Condition::Negative => "N",
+            Condition::NotNegative => "NN",
+            Condition::Carry => "C",
+            Condition::NotCarry => "NC",
+        };
+        write!(f, "{name}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble::assemble;
+    use crate::parse::parse;
+
+    /// Every instruction disassembles to source that assembles back to the
+    /// same bytes.
+    #[test]
+    fn round_trip() {
+        for b0 in 0..=0xFF {
+            for b1 in [0x00, 0x01, 0x02, 0x07, 0x10, 0x7F, 0x80, 0xF0, 0xFE, 0xFF] {
+                let Some(line) = disassemble(&[b0, b1, 0x12]).into_iter().next() else { continue };
+                let allow = if line.op.faults() { ".allow fault\n" } else { "" };
+                let src = format!("{allow}{}\n", line.op);
+                let program = assemble(&parse(&src).unwrap()).unwrap();
+                assert_eq!(program.image(), line.bytes, "{src}");
+            }
+        }
+    }
+
+    #[test]
+    fn calls() {
+        assert_eq!(Opcode::Call(Target::U11(0x7F0)).to_string(), "CALL 0xFFF0");
+        assert_eq!(Opcode::Call(Target::U11(0x3F0)).to_string(), "CALL 0x03F0");
+    }
+}
@@ -0,0 +1,585 @@
+//! Encoder, decoder and simulator for the CORA-16 instruction set, shared by
+//! the `asm` command line tool.
+
+pub mod assemble;
+pub mod cfg;
+pub mod debug;
+pub mod diag;
+pub mod disasm;
+pub mod link;
+pub mod lint;
+pub mod listing;
+mod macros;
+pub mod output;
+pub mod object;
+pub mod parse;
+pub mod sim;
+pub mod stack;
+
+/// One instruction of the CPU, as `src/decoder.v` sees it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Opcode {
+    /// A byte that is not an instruction; the CPU faults on it.
+    Text(u8),
+    Nop,
+    Halt,
+    Trap,
+    OutLo,
+    OutHi,
+    Push,
+    Pop,
+    Drop,
+    Return,
+    BranchIndirect,
+    CallIndirect,
+    Status,
+    /// `CALLW`, with the full address in a word after the opcode.
+    CallWord(u16),
+    /// `LOADW`, with the constant in a word after the opcode.
+    LoadImmediateWord(u16),
+    Not,
+    SetDataPointer,
+    Test,
+    /// Loads through the accumulator, which holds the offset.
+    LoadIndirect(RelativeTo, AddressingMode),
+    Load(Source),
+    Store(Source),
+    Add(Source),
+    Sub(Source),
+    And(Source),
+    Or(Source),
+    Xor(Source),
+    Shift(Direction, ShiftSource),
+    Branch(Target),
+    Call(Target),
+    If(Condition),
+}
+
+impl Opcode {
+    /// The bytes of the instruction, or why its operand does not fit.
+    pub fn encode(&self) -> Result<Encoded, EncodeError> {
+        let encoded = match self {
+            Opcode::Text(v) => Encoded::U8(*v),
+            Opcode::Nop => Encoded::U8(0x00),
+            Opcode::Halt => Encoded::U8(0x01),
+            Opcode::Trap => Encoded::U8(0x02),
+            Opcode::Drop => Encoded::U8(0x03),
+            Opcode::Push => Encoded::U8(0x04),
+            Opcode::Pop => Encoded::U8(0x05),
+            Opcode::Return => Encoded::U8(0x06),
+            Opcode::Not => Encoded::U8(0x07),
+            Opcode::OutLo => Encoded::U8(0x08),
+            Opcode::OutHi => Encoded::U8(0x09),
+            Opcode::SetDataPointer => Encoded::U8(0x0A),
+            Opcode::Test => Encoded::U8(0x0B),
+            Opcode::BranchIndirect => Encoded::U8(0x0C),
+            Opcode::CallIndirect => Encoded::U8(0x0D),
+            Opcode::Status => Encoded::U8(0x10),
+            Opcode::CallWord(w) => Encoded::U8U16(0x3E, *w),
+            Opcode::LoadImmediateWord(w) => Encoded::U8U16(0x3F, *w),
+            Opcode::LoadIndirect(r, m) => Encoded::U8(0x44 | r.encode() | m.encode()),
+            Opcode::Load(s) => s.encode(0x80),
+            Opcode::Store(s) => s.encode(0x90),
+            Opcode::Add(s) => s.encode(0x88),
+            Opcode::Sub(s) => s.encode(0x98),
+            Opcode::And(s) => s.encode(0xA0),
+            Opcode::Or(s) => s.encode(0xA8),
+            Opcode::Xor(s) => s.encode(0xB0),
+            Opcode::Shift(d, s) => s.encode(0xB8, *d).map_err(|e| e.within(self))?,
+            Opcode::Branch(t) => t.encode(0xC0).map_err(|e| e.within(self))?,
+            Opcode::Call(t) => t.encode(0xD0).map_err(|e| e.within(self))?,
+            Opcode::If(c) => c.encode(0xF0),
+        };
+        Ok(encoded)
+    }
+
+    /// Whether the CPU faults instead of running the instruction: `Text` is
+    /// no instruction at all, and `Store` needs an address to store to.
+    pub fn faults(&self) -> bool {
+        matches!(self, Opcode::Text(_) | Opcode::Store(Source::Const(..) | Source::Data(_)))
+    }
+
+    /// Whether a pending skip stops the instruction.  `Nop`, `If` and
+    /// `Status` run regardless, so a skipped `Status` still loads the flags.
+    pub fn skippable(&self) -> bool {
+        !matches!(self, Opcode::Nop | Opcode::If(_) | Opcode::Status)
+    }
+
+    /// The instruction at the start of `bytes`, if they hold a whole one.
+    /// `Text` is never decoded; unknown bytes give `None`.
+    pub fn decode(bytes: &[u8]) -> Option<Opcode> {
+        let b0 = *bytes.first()?;
+
+        if b0 & 0x80 == 0 {
+            let op = match b0 {
+                0x00 => Opcode::Nop,
+                0x01 => Opcode::Halt,
+                0x02 => Opcode::Trap,
+                0x03 => Opcode::Drop,
+                0x04 => Opcode::Push,
+                0x05 => Opcode::Pop,
+                0x06 => Opcode::Return,
+                0x07 => Opcode::Not,
+                0x08 => Opcode::OutLo,
+                0x09 => Opcode::OutHi,
+                0x0A => Opcode::SetDataPointer,
+                0x0B => Opcode::Test,
+                0x0C => Opcode::BranchIndirect,
+                0x0D => Opcode::CallIndirect,
+                0x10 => Opcode::Status,
+                0x3E | 0x3F => {
+                    let w = u16::from_be_bytes([*bytes.get(1)?, *bytes.get(2)?]);
+                    if b0 == 0x3E {
+                        Opcode::CallWord(w)
+                    } else {
+                        Opcode::LoadImmediateWord(w)
+                    }
+                }
+                0x44..=0x47 => Opcode::LoadIndirect(RelativeTo::decode(b0), AddressingMode::decode(b0)),
+                _ => return None,
+            };
+            return Some(op);
+        }
+
+        let b1 = *bytes.get(1)?;
+        let op = match b0 & 0xF8 {
+            0x80 => Opcode::Load(Source::decode(b0, b1)),
+            0x90 => Opcode::Store(Source::decode(b0, b1)),
+            0x88 => Opcode::Add(Source::decode(b0, b1)),
+            0x98 => Opcode::Sub(Source::decode(b0, b1)),
+            0xA0 => Opcode::And(Source::decode(b0, b1)),
+            0xA8 => Opcode::Or(Source::decode(b0, b1)),
+            0xB0 => Opcode::Xor(Source::decode(b0, b1)),
+            0xB8 => {
+                let (d, s) = ShiftSource::decode(b0, b1);
+                Opcode::Shift(d, s)
+            }
+            0xC0 => Opcode::Branch(Target::decode_signed(b0, b1)),
+            0xD0 => Opcode::Call(Target::decode_unsigned(b0, b1)),
+            0xF0 if b0 == 0xF0 => Opcode::If(Condition::decode(b1)?),
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    /// Like `decode`, but only if the instruction encodes back to the same
+    /// bytes, which it returns as well.  Bytes that set bits the encoder
+    /// never does are not taken for an instruction.
+    pub fn decode_exact(bytes: &[u8]) -> Option<(Opcode, Vec<u8>)> {
+        let op = Opcode::decode(bytes)?;
+        let encoded = op.encode().ok()?.bytes();
+        bytes.starts_with(&encoded).then_some((op, encoded))
+    }
+}
+
+/// The operand of a load, store or ALU instruction: a constant byte, the
+/// input port, or a word in memory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    /// A byte placed in the low or high half of the word.
+    Const(ByteInWord, u8),
+    Data(ByteInWord),
+    Ram(RelativeTo, AddressingMode, u8),
+}
+
+impl Source {
+    fn encode(&self, op: u8) -> Encoded {
+        let mut res = u16::from(op) << 8;
+        res |= match self {
+            Source::Const(b, c) => b.encode() | u16::from(*c),
+            Source::Data(b) => 0x0200 | b.encode(),
+            Source::Ram(r, m, a) => {
+                let opcode = 0x0400;
+                let relative = u16::from(r.encode()) << 8;
+                let mode = u16::from(m.encode()) << 8;
+                let addr = u16::from(*a);
+                opcode | relative | mode | addr
+            }
+        };
+        Encoded::U16(res)
+    }
+
+    fn decode(b0: u8, b1: u8) -> Source {
+        match b0 & 0x07 {
+            0 => Source::Const(ByteInWord::Lo, b1),
+            1 => Source::Const(ByteInWord::Hi, b1),
+            2 => Source::Data(ByteInWord::Lo),
+            3 => Source::Data(ByteInWord::Hi),
+            _ => Source::Ram(RelativeTo::decode(b0), AddressingMode::decode(b0), b1),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Left,
+    Right,
+}
+
+impl Direction {
+    fn encode(&self) -> u16 {
+        match self {
+            Direction::Left => 0,
+            Direction::Right => 1,
+        }
+    }
+}
+
+/// The shift count.  There is no high-byte constant, and a memory offset
+/// must be even, since the direction takes its low bit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShiftSource {
+    Const(u8),
+    Data,
+    Ram(RelativeTo, AddressingMode, u8),
+}
+
+impl ShiftSource {
+    fn encode(&self, op: u8, d: Direction) -> Result<Encoded, OperandError> {
+        let mut res = u16::from(op) << 8;
+        res |= match self {
+            ShiftSource::Const(c) => (d.encode() << 8) | u16::from(*c),
+            // Only a memory operand keeps the direction in bit 0, where its
+            // even offset leaves room; otherwise `src/decoder.v` reads it
+            // from bit 8.
+            ShiftSource::Data => 0x0200 | (d.encode() << 8),
+            ShiftSource::Ram(r, m, a) => {
+                if a & 1 != 0 {
+                    return Err(OperandError {
+                        operand: "shift source address",
+                        value: i64::from(*a),
+                        legal: Legal::Even(0x00, 0xFE),
+                    });
+                }
+                let opcode = 0x0400;
+                let relative = u16::from(r.encode()) << 8;
+                let mode = u16::from(m.encode()) << 8;
+                let addr = u16::from(*a);
+                opcode | relative | mode | addr | d.encode()
+            }
+        };
+        Ok(Encoded::U16(res))
+    }
+
+    fn decode(b0: u8, b1: u8) -> (Direction, ShiftSource) {
+        let (d, s) = match b0 & 0x07 {
+            0 => (b0, ShiftSource::Const(b1)),
+            1 => (b0, ShiftSource::Const(b1)),
+            2 | 3 => (b0, ShiftSource::Data),
+            _ => (b1, ShiftSource::Ram(RelativeTo::decode(b0), AddressingMode::decode(b0), b1 & 0xFE)),
+        };
+        let d = if d & 1 == 0 { Direction::Left } else { Direction::Right };
+        (d, s)
+    }
+}
+
+/// The register a memory operand's offset is added to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelativeTo {
+    DataPointer,
+    StackPointer,
+}
+
+impl RelativeTo {
+    fn encode(&self) -> u8 {
+        match self {
+            RelativeTo::DataPointer => 0,
+            RelativeTo::StackPointer => 2,
+        }
+    }
+
+    fn decode(op: u8) -> RelativeTo {
+        if op & 2 == 0 {
+            RelativeTo::DataPointer
+        } else {
+            RelativeTo::StackPointer
+        }
+    }
+}
+
+/// Whether a memory operand is the word at the address, or the word that
+/// one points to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressingMode {
+    Direct,
+    Indirect,
+}
+
+impl AddressingMode {
+    fn encode(&self) -> u8 {
+        match self {
+            AddressingMode::Direct => 0,
+            AddressingMode::Indirect => 1,
+        }
+    }
+
+    fn decode(op: u8) -> AddressingMode {
+        if op & 1 == 0 {
+            AddressingMode::Direct
+        } else {
+            AddressingMode::Indirect
+        }
+    }
+}
+
+/// Which half of the word a constant byte or the input port goes to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteInWord {
+    Lo,
+    Hi,
+}
+
+impl ByteInWord {
+    fn encode(&self) -> u16 {
+        match self {
+            ByteInWord::Lo => 0x0000,
+            ByteInWord::Hi => 0x0100,
+        }
+    }
+}
+
+/// The eleven bits of a branch or call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    /// An offset from the next instruction.
+    I11(i16),
+    /// An address, sign-extended by the decoder to the first or last KiB.
+    U11(u16),
+}
+
+impl Target {
+    fn encode(&self, op: u8) -> Result<Encoded, OperandError> {
+        let mut res = u16::from(op) << 8;
+        res |= match self {
+            Target::I11(v) => {
+                if !(-0x400..=0x3FF).contains(v) {
+                    return Err(OperandError {
+                        operand: "I11 offset",
+                        value: i64::from(*v),
+                        legal: Legal::Range(-0x400, 0x3FF),
+                    });
+                }
+                (*v as u16) & 0x07FF
+            }
+            Target::U11(v) => {
+                if *v > 0x7FF {
+                    return Err(OperandError {
+                        operand: "U11 address",
+                        value: i64::from(*v),
+                        legal: Legal::Range(0, 0x7FF),
+                    });
+                }
+                *v
+            }
+        };
+        Ok(Encoded::U16(res))
+    }
+
+    /// The `U11` for a `Call` to `addr`, if the eleven bits reach it: only
+    /// the first and last KiB of memory do.
+    pub fn call(addr: u16) -> Option<Target> {
+        (!(0x400..0xFC00).contains(&addr)).then_some(Target::U11(addr & 0x7FF))
+    }
+
+    /// The eleven bits sign-extended to a word, as the decoder does: the
+    /// address of a `Call`, or the offset of a `Branch`.
+    pub fn extended(&self) -> u16 {
+        let raw = match *self {
+            Target::I11(v) => v as u16,
+            Target::U11(v) => v,
+        } & 0x07FF;
+        if raw & 0x0400 != 0 {
+            raw | 0xF800
+        } else {
+            raw
+        }
+    }
+
+    fn decode_signed(b0: u8, b1: u8) -> Target {
+        let raw = (u16::from(b0 & 0x07) << 8) | u16::from(b1);
+        Target::I11(((raw << 5) as i16) >> 5)
+    }
+
+    fn decode_unsigned(b0: u8, b1: u8) -> Target {
+        Target::U11((u16::from(b0 & 0x07) << 8) | u16::from(b1))
+    }
+}
+
+/// What an `If` tests.  The instruction after it is skipped unless the
+/// condition holds; `Else` holds when the instruction before was skipped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Condition {
+    Zero,
+    NotZero,
+    Else,
+    NotElse,
+    Negative,
+    NotNegative,
+    Carry,
+    NotCarry,
+}
+
+impl Condition {
+    fn encode(&self, op: u8) -> Encoded {
+        let mut res = u16::from(op) << 8;
+        res |= match self {
+            Condition::Zero        => 0x0000,
+            Condition::NotZero     => 0x0001,
+            Condition::Else        => 0x0002,
+            Condition::NotElse     => 0x0003,
+            Condition::Negative    => 0x0004,
+            Condition::NotNegative => 0x0005,
+            Condition::Carry       => 0x0006,
+            Condition::NotCarry    => 0x0007,
+        };
+        Encoded::U16(res)
+    }
+
+    fn decode(b1: u8) -> Option<Condition> {
+        let c = match b1 {
+            0x00 => Condition::Zero,
+            0x01 => Condition::NotZero,
+            0x02 => Condition::Else,
+            0x03 => Condition::NotElse,
+            0x04 => Condition::Negative,
+            0x05 => Condition::NotNegative,
+            0x06 => Condition::Carry,
+            0x07 => Condition::NotCarry,
+            _ => return None,
+        };
+        Some(c)
+    }
+}
+
+/// An encoded instruction: a byte, a big-endian word, or a byte followed by
+/// a word.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Encoded {
+    U16(u16),
+    U8(u8),
+    U8U16(u8, u16),
+}
+
+// An encoding is never empty.
+#[allow(clippy::len_without_is_empty)]
+impl Encoded {
+    pub fn len(&self) -> usize {
+        match self {
+            Encoded::U8(_) => 1,
+            Encoded::U16(_) => 2,
+            Encoded::U8U16(_, _) => 3,
+        }
+    }
+
+    pub fn bytes(&self) -> Vec<u8> {
+        match self {
+            Encoded::U8(b) => vec![*b],
+            Encoded::U16(w) => w.to_be_bytes().to_vec(),
+            Encoded::U8U16(b, w) => {
+                let [hi, lo] = w.to_be_bytes();
+                vec![*b, hi, lo]
+            }
+        }
+    }
+}
+
+/// An operand that does not fit the field it is encoded into.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncodeError {
+    /// The instruction, in assembler syntax.
+    pub inst: String,
+    pub operand: &'static str,
+    pub value: i64,
+    pub legal: Legal,
+}
+
+impl std::fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}: {} {} ", self.inst, self.operand, self.value)?;
+        match self.legal {
+            Legal::Range(lo, hi) => write!(f, "is outside the range {lo}..={hi}"),
+            Legal::Even(lo, hi) => write!(f, "must be an even address in {lo:#04X}..={hi:#04X}"),
+        }
+    }
+}
+
+impl std::error::Error for EncodeError {}
+
+/// The values an operand may take.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Legal {
+    Range(i64, i64),
+    Even(i64, i64),
+}
+
+struct OperandError {
+    operand: &'static str,
+    value: i64,
+    legal: Legal,
+}
+
+impl OperandError {
+    fn within(self, inst: &Opcode) -> EncodeError {
+        EncodeError {
+            inst: inst.to_string(),
+            operand: self.operand,
+            value: self.value,
+            legal: self.legal,
+        }
+    }
+}
+
+/// Serializes a program into the bytes of a memory image starting at
+/// address 0.
+pub fn to_bytes(insts: &[Opcode]) -> Result<Vec<u8>, EncodeError> {
+    let mut bytes = vec![];
+    for inst in insts {
+        bytes.extend(inst.encode()?.bytes());
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn operand_errors() {
+        let err = Opcode::Branch(Target::I11(0x500)).encode().unwrap_err();
+        assert_eq!(err.to_string(), "BR 1280: I11 offset 1280 is outside the range -1024..=1023");
+        let shift = Opcode::Shift(Direction::Left, ShiftSource::Ram(RelativeTo::DataPointer, AddressingMode::Direct, 3));
+        let err = shift.encode().unwrap_err();
+        assert_eq!(err.to_string(), "SHL [dp+0x03]: shift source address 3 must be an even address in 0x00..=0xFE");
+    }
+
+    #[test]
+    fn targets() {
+        assert_eq!(Target::U11(0x7F0).extended(), 0xFFF0);
+        assert_eq!(Target::U11(0x3F0).extended(), 0x03F0);
+        assert_eq!(Target::I11(-2).extended(), 0xFFFE);
+        assert_eq!(Target::call(0xFFF0), Some(Target::U11(0x7F0)));
+        assert_eq!(Target::call(0x03FF), Some(Target::U11(0x3FF)));
+        assert_eq!(Target::call(0x0400), None);
+        assert_eq!(Target::call(0xFBFF), None);
+    }
+
+    #[test]
+    fn decode_exact() {
+        let load = Opcode::Load(Source::Data(ByteInWord::Lo));
+        assert_eq!(Opcode::decode_exact(&[0x82, 0x00, 0xFF]), Some((load.clone(), vec![0x82, 0x00])));
+        // The second byte of `LOAD in` is ignored, but never anything but zero.
+        assert_eq!(Opcode::decode(&[0x82, 0x05]), Some(load));
+        assert_eq!(Opcode::decode_exact(&[0x82, 0x05]), None);
+    }
+
+    #[test]
+    fn shift_direction() {
+        let shr = |s| Opcode::Shift(Direction::Right, s).encode().unwrap();
+        assert_eq!(shr(ShiftSource::Const(4)), Encoded::U16(0xB904));
+        assert_eq!(shr(ShiftSource::Data), Encoded::U16(0xBB00));
+        let ram = ShiftSource::Ram(RelativeTo::DataPointer, AddressingMode::Direct, 2);
+        assert_eq!(shr(ram.clone()), Encoded::U16(0xBC03));
+        assert_eq!(Opcode::decode(&[0xBB, 0x00]), Some(Opcode::Shift(Direction::Right, ShiftSource::Data)));
+        assert_eq!(Opcode::decode(&[0xBC, 0x03]), Some(Opcode::Shift(Direction::Right, ram)));
+    }
+}
@@ -0,0 +1,234 @@
+//! Places the sections of relocatable objects in memory, one after another
+//! in the order given, and fills in every field that refers to them.
+
+use std::collections::HashMap;
+
+use crate::assemble::{word, Layout, Section};
+use crate::debug::{self, DebugInfo};
+use crate::object::{Object, RelocKind};
+use crate::Target;
+
+/// Everything that kept the objects from linking.
+#[derive(Debug)]
+pub struct LinkError {
+    pub messages: Vec<String>,
+}
+
+impl std::fmt::Display for LinkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.messages.join("\n"))
+    }
+}
+
+impl std::error::Error for LinkError {}
+
+const SECTIONS: [Section; 3] = [Section::Text, Section::Data, Section::Bss];
+
+/// A linked memory image, with every object's labels and source lines at
+/// their final addresses.
+pub struct Linked {
+    pub image: Vec<u8>,
+    pub debug: DebugInfo,
+}
+
+/// Links named objects into a memory image.  The first object's `.text`
+/// starts at the layout's code address, so it holds the entry point.
+pub fn link(objects: &[(String, Object)], layout: &Layout) -> Result<Linked, LinkError> {
+    let mut messages = vec![];
+
+    // Each object's piece of a section follows the previous object's,
+    // and each section follows the one before it unless the layout says
+    // otherwise.
+    let mut bases = vec![[0; 3]; objects.len()];
+    let mut ranges = [0..0, 0..0, 0..0];
+    let mut next = usize::from(layout.code);
+    for section in SECTIONS {
+        let s = section as usize;
+        let start = match section {
+            Section::Text => usize::from(layout.code),
+            Section::Data => layout.data.map_or(next.next_multiple_of(2), usize::from),
+            Section::Bss => layout.bss.map_or(next.next_multiple_of(2), usize::from),
+        };
+        let mut pc = start;
+        for ((_, object), base) in objects.iter().zip(&mut bases) {
+            let align = if section == Section::Text { 1 } else { 2 };
+            pc = pc.next_multiple_of(object.aligns[s].max(align));
+            base[s] = pc;
+            pc += object.sections[s].len();
+        }
+        if pc > 0x10000 {
+            messages.push(format!("{} does not fit in memory", section.name()));
+        }
+        ranges[s] = start..pc;
+        next = pc;
+    }
+    if !messages.is_empty() {
+        return Err(LinkError { messages });
+    }
+
+    for (i, a) in SECTIONS.iter().enumerate() {
+        for b in &SECTIONS[i + 1..] {
+            let (ra, rb) = (&ranges[*a as usize], &ranges[*b as usize]);
+            if !ra.is_empty() && !rb.is_empty() && ra.start < rb.end && rb.start < ra.end {
+                messages.push(format!("{} at {:#06X} overlaps {} at {:#06X}", b.name(), rb.start, a.name(), ra.start));
+            }
+        }
+    }
+    let stack = layout.stack_top - layout.stack_size..layout.stack_top;
+    for section in SECTIONS {
+        let range = &ranges[section as usize];
+        if !range.is_empty() && range.start < stack.end && stack.start < range.end {
+            messages.push(format!(
+                "{} at {:#06X} runs into the stack at {:#06X}..{:#06X}",
+                section.name(),
+                range.start.max(stack.start),
+                stack.start,
+                stack.end
+            ));
+        }
+    }
+
+    let mut globals: HashMap<&str, (&str, usize)> = HashMap::new();
+    for ((name, object), base) in objects.iter().zip(&bases) {
+        for symbol in object.symbols.iter().filter(|symbol| symbol.global) {
+            let addr = base[symbol.section as usize] + usize::from(symbol.offset);
+            if let Some((other, _)) = globals.insert(&symbol.name, (name, addr)) {
+                messages.push(format!("symbol `{}` is defined in both {other} and {name}", symbol.name));
+            }
+        }
+    }
+
+    let len = ranges[0].end.max(if ranges[1].is_empty() { 0 } else { ranges[1].end });
+    let mut image = vec![layout.fill; len];
+    image[ranges[2].start.min(len)..ranges[2].end.min(len)].fill(0);
+    for ((_, object), base) in objects.iter().zip(&bases) {
+        for section in [Section::Text, Section::Data] {
+            // An empty section may be aligned past the end of the image.
+            let bytes = &object.sections[section as usize];
+            if bytes.is_empty() {
+                continue;
+            }
+            let start = base[section as usize];
+            image[start..start + bytes.len()].copy_from_slice(bytes);
+        }
+    }
+
+    for ((name, object), base) in objects.iter().zip(&bases) {
+        for reloc in &object.relocs {
+            let target = match SECTIONS.iter().find(|s| s.name() == reloc.symbol) {
+                Some(section) => base[*section as usize],
+                None => match globals.get(reloc.symbol.as_str()) {
+                    Some((_, addr)) => *addr,
+                    None => {
+                        let message = format!("{name}: undefined symbol `{}`", reloc.symbol);
+                        if !messages.contains(&message) {
+                            messages.push(message);
+                        }
+                        continue;
+                    }
+                },
+            };
+            let at = base[reloc.section as usize] + usize::from(reloc.offset);
+            let value = target as i64 + reloc.addend;
+            if let Err(message) = patch(&mut image, at, reloc.kind, value) {
+                messages.push(format!("{name}: {at:#06X}: {message} (referring to `{}`)", reloc.symbol));
+            }
+        }
+    }
+
+    if !messages.is_empty() {
+        return Err(LinkError { messages });
+    }
+
+    let mut debug = DebugInfo::default();
+    for ((name, object), base) in objects.iter().zip(&bases) {
+        let placed = object
+            .symbols
+            .iter()
+            .map(|symbol| (symbol.section, (base[symbol.section as usize] + usize::from(symbol.offset)) as u16))
+            .collect::<Vec<_>>();
+        let ends = SECTIONS.map(|section| base[section as usize] + object.sections[section as usize].len());
+        for ((symbol, (section, addr)), size) in object.symbols.iter().zip(&placed).zip(debug::sizes(&placed, ends)) {
+            debug.labels.push(debug::Label { name: symbol.name.clone(), section: *section, addr: *addr, size });
+        }
+        for line in &object.lines {
+            debug.lines.push(debug::Line {
+                addr: (base[line.section as usize] + usize::from(line.offset)) as u16,
+                size: line.size,
+                file: object.source.clone().unwrap_or_else(|| name.clone()),
+                line: line.line,
+                expanded: line.expanded,
+            });
+        }
+    }
+    debug.lines.sort_by_key(|line| line.addr);
+    Ok(Linked { image, debug })
+}
+
+/// Fills in the field of `kind` at `at` with `value`.
+fn patch(image: &mut [u8], at: usize, kind: RelocKind, value: i64) -> Result<(), String> {
+    let size = if matches!(kind, RelocKind::Hi | RelocKind::Lo) { 1 } else { 2 };
+    let field = image.get_mut(at..at + size).ok_or("field lies outside the image")?;
+    let eleven = |field: &mut [u8], v: u16| {
+        field[0] = (field[0] & 0xF8) | (v >> 8) as u8 & 0x07;
+        field[1] = v as u8;
+    };
+    match kind {
+        RelocKind::Call => {
+            let addr = word(value)?;
+            let Some(Target::U11(bits)) = Target::call(addr) else {
+                return Err(format!("`Call` cannot reach {addr:#06X}; use `CALLW`"));
+            };
+            eleven(field, bits);
+        }
+        RelocKind::Branch => {
+            let offset = value - (at as i64 + 2);
+            if !(-0x400..=0x3FF).contains(&offset) {
+                return Err(format!("branch offset {offset} is outside the range -1024..=1023"));
+            }
+            eleven(field, offset as u16 & 0x7FF);
+        }
+        RelocKind::Word => field[..2].copy_from_slice(&word(value)?.to_be_bytes()),
+        RelocKind::Hi => field[0] = (word(value)? >> 8) as u8,
+        RelocKind::Lo => field[0] = word(value)? as u8,
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble::assemble_object;
+    use crate::parse::parse;
+
+    fn link_sources(sources: &[(&str, &str)]) -> Result<Vec<u8>, Vec<String>> {
+        let objects = sources
+            .iter()
+            .map(|(name, src)| (name.to_string(), assemble_object(&parse(src).unwrap()).unwrap()))
+            .collect::<Vec<_>>();
+        link(&objects, &Layout::default()).map(|linked| linked.image).map_err(|e| e.messages)
+    }
+
+    #[test]
+    fn relocations() {
+        let main = "CALL f\nLI data\nBR f\nHALT\n";
+        let lib = ".global f, data\nf:\nRET\n.data\ndata:\n.word f\n";
+        let image = link_sources(&[("main", main), ("lib", lib)]).unwrap();
+        assert_eq!(image, [0xD0, 0x08, 0x3F, 0x00, 0x0A, 0xC0, 0x01, 0x01, 0x06, 0x00, 0x00, 0x08]);
+    }
+
+    #[test]
+    fn symbols() {
+        let f = ".global f\nf:\nRET\n";
+        let messages = link_sources(&[("main", "CALL f\nCALL g\nBR g\n"), ("a", f), ("b", f)]).unwrap_err();
+        assert_eq!(messages, ["symbol `f` is defined in both a and b", "main: undefined symbol `g`"]);
+    }
+
+    #[test]
+    fn reach() {
+        let far = ".global f\n.space 0x400\nf:\nRET\n";
+        let messages = link_sources(&[("main", "CALL f\n"), ("lib", far)]).unwrap_err();
+        assert_eq!(messages, ["main: 0x0000: `Call` cannot reach 0x0402; use `CALLW` (referring to `f`)"]);
+        assert!(link_sources(&[("main", "CALLW f\n"), ("lib", far)]).is_ok());
+    }
+}
@@ -0,0 +1,446 @@
+//! Checks over an assembled program for code that assembles but most likely
+//! does not do what was meant.  They only warn.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+
+use crate::assemble::{Item, Program, Section};
+use crate::diag::Diagnostic;
+use crate::{Condition, Opcode, Target};
+
+/// How many instructions may run between setting a flag and testing it
+/// before the test is worth a second look.
+const FAR: usize = 8;
+
+/// Every warning for `program`, in line order.
+pub fn lint(program: &Program) -> Vec<Diagnostic> {
+    let mut warnings = flags(program);
+    warnings.extend(skips(program));
+    warnings.sort_by_key(|diag| diag.line);
+    warnings
+}
+
+/// The status column of the ISA table in `docs/info.md` for `op`.
+pub fn status(op: &Opcode) -> &'static str {
+    match op {
+        Opcode::Not => "---- -1##",
+        Opcode::Test | Opcode::And(_) | Opcode::Or(_) | Opcode::Xor(_) => "---- -0##",
+        Opcode::Add(_) | Opcode::Sub(_) | Opcode::Shift(..) => "---- -###",
+        _ => "---- ----",
+    }
+}
+
+/// The flags set by ALU instructions, in the order of the status byte.
+/// `E` is not one of them: every instruction sets it to whether it was
+/// skipped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flag {
+    Carry,
+    Negative,
+    Zero,
+}
+
+impl Flag {
+    const ALL: [Flag; 3] = [Flag::Carry, Flag::Negative, Flag::Zero];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Flag::Carry => "C",
+            Flag::Negative => "N",
+            Flag::Zero => "Z",
+        }
+    }
+
+    /// Whether `op` may change the flag, according to `status`.
+    pub fn written_by(&self, op: &Opcode) -> bool {
+        status(op).as_bytes()[6 + *self as usize] != b'-'
+    }
+
+    /// The flag an `IF` tests, unless it tests `E`.
+    pub fn read_by(cond: &Condition) -> Option<Flag> {
+        match cond {
+            Condition::Zero | Condition::NotZero => Some(Flag::Zero),
+            Condition::Negative | Condition::NotNegative => Some(Flag::Negative),
+            Condition::Carry | Condition::NotCarry => Some(Flag::Carry),
+            Condition::Else | Condition::NotElse => None,
+        }
+    }
+}
+
+/// What may have set a flag last.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Writer {
+    /// Nothing since reset.
+    Reset,
+    /// Whoever called the subroutine starting at this instruction.
+    Caller(usize),
+    /// This instruction, or the subroutine it calls.
+    Inst(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Reach {
+    /// The most instructions run since the writer on any path, up to
+    /// `FAR + 1`.
+    distance: usize,
+    /// The last instruction since then to load the accumulator without
+    /// setting the flags.
+    load: Option<usize>,
+}
+
+/// What is known on entry to an instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct State {
+    flags: [BTreeMap<Writer, Reach>; 3],
+    /// The `IF`s that may decide whether the instruction is skipped, with
+    /// `None` for paths on which nothing does.
+    guard: BTreeSet<Option<usize>>,
+    /// The same for the instruction before it, which `E` reports on.
+    skipped: BTreeSet<Option<usize>>,
+}
+
+impl State {
+    fn entry(writer: Writer) -> State {
+        let flag = BTreeMap::from([(writer, Reach { distance: 0, load: None })]);
+        State { flags: [flag.clone(), flag.clone(), flag], guard: [None].into(), skipped: [None].into() }
+    }
+
+    /// The state after instruction `i` runs.
+    fn after(&self, i: usize, op: &Opcode) -> State {
+        let call = matches!(op, Opcode::Call(_) | Opcode::CallWord(_) | Opcode::CallIndirect);
+        let loads = matches!(op, Opcode::Load(_) | Opcode::LoadIndirect(..) | Opcode::LoadImmediateWord(_) | Opcode::Pop);
+        let flags = Flag::ALL.map(|flag| {
+            if call || flag.written_by(op) {
+                return BTreeMap::from([(Writer::Inst(i), Reach { distance: 0, load: None })]);
+            }
+            self.flags[flag as usize]
+                .iter()
+                .map(|(writer, reach)| {
+                    let load = if loads { Some(i) } else { reach.load };
+                    (*writer, Reach { distance: (reach.distance + 1).min(FAR + 1), load })
+                })
+                .collect()
+        });
+        let guard = [if matches!(op, Opcode::If(_)) { Some(i) } else { None }].into();
+        State { flags, guard, skipped: self.guard.clone() }
+    }
+
+    /// The state after `IF` `i` skips the instruction following it.
+    fn skipping(&self, i: usize, op: &Opcode) -> State {
+        let mut state = self.after(i, op);
+        for flag in &mut state.flags {
+            for reach in flag.values_mut() {
+                reach.distance = (reach.distance + 1).min(FAR + 1);
+            }
+        }
+        State { guard: [None].into(), skipped: [Some(i)].into(), ..state }
+    }
+
+    /// Merges `other` in, returning whether anything changed.
+    fn merge(&mut self, other: &State) -> bool {
+        let before = self.clone();
+        for (mine, theirs) in self.flags.iter_mut().zip(&other.flags) {
+            for (writer, reach) in theirs {
+                let merged = mine.entry(*writer).or_insert(*reach);
+                merged.distance = merged.distance.max(reach.distance);
+                merged.load = merged.load.or(reach.load);
+            }
+        }
+        self.guard.extend(&other.guard);
+        self.skipped.extend(&other.skipped);
+        *self != before
+    }
+}
+
+/// The instructions in `.text`, in address order.
+struct Code<'a> {
+    insts: Vec<(&'a Item, &'a Opcode)>,
+    at: HashMap<u16, usize>,
+}
+
+impl<'a> Code<'a> {
+    fn new(program: &'a Program) -> Code<'a> {
+        let mut insts = program
+            .items
+            .iter()
+            .filter(|item| item.section == Section::Text)
+            .filter_map(|item| Some((item, item.op.as_ref()?)))
+            .collect::<Vec<_>>();
+        insts.sort_by_key(|(item, _)| item.addr);
+        let at = insts.iter().enumerate().map(|(i, (item, _))| (item.addr, i)).collect();
+        Code { insts, at }
+    }
+
+    /// The instruction after `i` in memory.
+    fn next(&self, i: usize) -> Option<usize> {
+        let (item, _) = self.insts[i];
+        self.at.get(&item.addr.wrapping_add(item.bytes.len() as u16)).copied()
+    }
+
+    /// Where a direct branch or call at `i` goes.
+    fn target(&self, i: usize) -> Option<usize> {
+        let (item, op) = self.insts[i];
+        let addr = match op {
+            Opcode::Branch(Target::I11(offset)) => item.addr.wrapping_add(2).wrapping_add(*offset as u16),
+            Opcode::Call(target @ Target::U11(_)) => target.extended(),
+            Opcode::CallWord(addr) => *addr,
+            _ => return None,
+        };
+        self.at.get(&addr).copied()
+    }
+
+    /// The instructions that may run after `i`, and whether each is reached
+    /// by `i` skipping the one after it.
+    fn successors(&self, i: usize) -> Vec<(usize, bool)> {
+        match self.insts[i].1 {
+            Opcode::If(_) => {
+                let next = self.next(i);
+                let skip = next.and_then(|next| self.next(next));
+                next.map(|n| (n, false)).into_iter().chain(skip.map(|n| (n, true))).collect()
+            }
+            Opcode::Branch(_) => self.target(i).map(|n| (n, false)).into_iter().collect(),
+            Opcode::Return | Opcode::Halt | Opcode::BranchIndirect => vec![],
+            _ => self.next(i).map(|n| (n, false)).into_iter().collect(),
+        }
+    }
+}
+
+/// Warns where an `IF` tests a flag that was not obviously set for it:
+/// never set, set differently along different paths, set before the
+/// accumulator was loaded with something else, or set long before.
+fn flags(program: &Program) -> Vec<Diagnostic> {
+    let code = Code::new(program);
+    let mut states: Vec<Option<State>> = vec![None; code.insts.len()];
+    let mut work = VecDeque::new();
+    let enter = |states: &mut Vec<Option<State>>, work: &mut VecDeque<usize>, i: usize, state: &State| {
+        let changed = match &mut states[i] {
+            Some(existing) => existing.merge(state),
+            slot => {
+                *slot = Some(state.clone());
+                true
+            }
+        };
+        if changed && !work.contains(&i) {
+            work.push_back(i);
+        }
+    };
+
+    // Reset starts the CPU at address 0 with the flags clear, wherever
+    // `.text` is placed.  A fill of zeros runs as `NOP`s up to whatever
+    // comes first.
+    let placed = program.items.iter().filter(|item| item.section != Section::Bss && !item.bytes.is_empty());
+    let reset = if program.fill == 0 { placed.map(|item| item.addr).min().unwrap_or(0) } else { 0 };
+    if let Some(&entry) = code.at.get(&reset) {
+        enter(&mut states, &mut work, entry, &State::entry(Writer::Reset));
+    }
+    for i in 0..code.insts.len() {
+        if matches!(code.insts[i].1, Opcode::Call(_) | Opcode::CallWord(_)) {
+            if let Some(callee) = code.target(i) {
+                enter(&mut states, &mut work, callee, &State::entry(Writer::Caller(callee)));
+            }
+        }
+    }
+    while let Some(i) = work.pop_front() {
+        let Some(state) = states[i].clone() else { continue };
+        let op = code.insts[i].1;
+        for (next, skip) in code.successors(i) {
+            let out = if skip { state.skipping(i, op) } else { state.after(i, op) };
+            enter(&mut states, &mut work, next, &out);
+        }
+    }
+
+    let describe = |i: usize| {
+        let (item, op) = code.insts[i];
+        format!("`{op}` on line {}", item.line)
+    };
+    let subroutine = |i: usize| {
+        let addr = code.insts[i].0.addr;
+        program
+            .labels
+            .iter()
+            .find(|label| label.section == Section::Text && label.addr == addr)
+            .map_or("this subroutine".into(), |label| format!("`{}`", label.name))
+    };
+
+    let mut warnings = vec![];
+    for (i, (item, op)) in code.insts.iter().enumerate() {
+        let (Opcode::If(cond), Some(state)) = (op, &states[i]) else { continue };
+        let warning = |message: String| warning(item, message);
+
+        let Some(flag) = Flag::read_by(cond) else {
+            // Guarding a `NOP` only leaves E set for what follows, as a long
+            // branch after an `IF` does.
+            if code.next(i).is_some_and(|next| *code.insts[next].1 == Opcode::Nop) {
+                continue;
+            }
+            let guarded = state.skipped.iter().filter(|g| g.is_some()).count();
+            let message = format!("`{op}` tests whether the instruction before it was skipped");
+            if guarded == 0 {
+                warnings.push(warning(format!("{message}, but no `IF` guards that instruction")));
+            } else if state.skipped.contains(&None) {
+                warnings.push(
+                    warning(format!("{message}, but it is only guarded by an `IF` on some paths"))
+                        .note("a branch to it skips the `IF`"),
+                );
+            }
+            continue;
+        };
+
+        let name = flag.name();
+        let writers = &state.flags[flag as usize];
+        let loads = |diag: Diagnostic, load: Option<usize>| match load {
+            Some(load) => diag.note(format!(
+                "{} loads the accumulator without setting the flags; `TEST` sets them from it",
+                describe(load)
+            )),
+            None => diag,
+        };
+        let warning = match writers.iter().collect::<Vec<_>>().as_slice() {
+            [] => continue,
+            [(Writer::Reset, reach)] => loads(
+                warning(format!("`{op}` tests {name}, but no instruction sets {name} before it"))
+                    .note("the flags are clear after reset"),
+                reach.load,
+            ),
+            [(Writer::Caller(entry), reach)] => loads(
+                warning(format!("`{op}` tests {name} as it was left by the caller of {}", subroutine(*entry))),
+                reach.load,
+            ),
+            [(Writer::Inst(w), reach)] => match reach.load {
+                Some(load) => loads(
+                    warning(format!(
+                        "`{op}` tests {name} as set by {}, not the value loaded since",
+                        describe(*w)
+                    )),
+                    Some(load),
+                ),
+                None if reach.distance > FAR => warning(format!(
+                    "`{op}` tests {name} as set by {}, more than {FAR} instructions earlier",
+                    describe(*w)
+                )),
+                None => continue,
+            },
+            many => {
+                let mut diag = warning(format!("`{op}` tests {name}, which is set by different instructions on different paths"));
+                for (writer, _) in many {
+                    diag = diag.note(match writer {
+                        Writer::Reset => "on one path nothing sets it after reset".into(),
+                        Writer::Caller(entry) => format!("on one path the caller of {} sets it", subroutine(*entry)),
+                        Writer::Inst(w) => format!("on one path {} sets it", describe(*w)),
+                    });
+                }
+                diag
+            }
+        };
+        warnings.push(warning);
+    }
+    warnings
+}
+
+/// Warns where an `IF` guards something a skip does not stop: `STATUS`,
+/// which loads the flags either way, another `IF`, whose condition replaces
+/// the pending skip, or data, which the CPU decodes as whatever instruction
+/// its bytes spell to know how far to skip.
+fn skips(program: &Program) -> Vec<Diagnostic> {
+    let text = program
+        .items
+        .iter()
+        .filter(|item| item.section == Section::Text && !item.bytes.is_empty())
+        .map(|item| (item.addr, item))
+        .collect::<BTreeMap<_, _>>();
+
+    let mut warnings = vec![];
+    for item in text.values() {
+        let Some(op @ Opcode::If(_)) = &item.op else { continue };
+        let Some(next) = text.get(&item.addr.wrapping_add(2)) else { continue };
+        let message = match &next.op {
+            None => format!("`{op}` guards data from line {}", next.line),
+            Some(guarded) if guarded.skippable() || *guarded == Opcode::Nop => continue,
+            Some(guarded @ Opcode::If(_)) => format!("`{op}` guards `{guarded}`, which is tested whether or not it is skipped"),
+            Some(guarded) => format!("`{op}` guards `{guarded}`, which runs whether or not it is skipped"),
+        };
+        let diag = warning(item, message);
+        warnings.push(match &next.op {
+            None => diag.note("the CPU skips as many bytes as the instruction the data decodes to"),
+            Some(Opcode::If(_)) => diag.note("only the second `IF` decides whether the instruction after it runs"),
+            Some(_) => diag,
+        });
+    }
+    warnings
+}
+
+/// A warning about `item`, which may have come from a macro.
+fn warning(item: &Item, message: String) -> Diagnostic {
+    let diag = Diagnostic::warning(item.line, message);
+    if item.expanded {
+        diag.note("in code expanded from this line")
+    } else {
+        diag
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble::{assemble, assemble_with, Layout};
+    use crate::parse::parse;
+
+    fn warnings(src: &str) -> Vec<(usize, String)> {
+        let program = assemble(&parse(src).unwrap()).unwrap();
+        lint(&program).into_iter().map(|diag| (diag.line, diag.message)).collect()
+    }
+
+    #[test]
+    fn flags() {
+        assert_eq!(warnings("LOAD #0x01\nTEST\nIF Z\nHALT\n"), []);
+        assert_eq!(warnings("IF Z\nHALT\n"), [(1, "`IF Z` tests Z, but no instruction sets Z before it".into())]);
+        assert_eq!(
+            warnings("TEST\nLOAD #0x01\nIF Z\nHALT\n"),
+            [(3, "`IF Z` tests Z as set by `TEST` on line 1, not the value loaded since".into())]
+        );
+        let far = format!("TEST\n{}IF C\nHALT\n", "OUT\n".repeat(FAR + 1));
+        assert_eq!(warnings(&far), [(11, "`IF C` tests C as set by `TEST` on line 1, more than 8 instructions earlier".into())]);
+        assert_eq!(
+            warnings("TEST\nIF Z\nBR join\nNOT\njoin:\nIF C\nHALT\n"),
+            [(6, "`IF C` tests C, which is set by different instructions on different paths".into())]
+        );
+        assert_eq!(
+            warnings("CALL sub\nHALT\nsub:\nIF N\nRET\n"),
+            [(4, "`IF N` tests N as it was left by the caller of `sub`".into())]
+        );
+    }
+
+    #[test]
+    fn else_flag() {
+        let message = "`IF E` tests whether the instruction before it was skipped, but no `IF` guards that instruction";
+        assert_eq!(warnings("NOP\nIF E\nHALT\n"), [(2, message.into())]);
+        assert_eq!(warnings("TEST\nIF Z\nOUT\nIF E\nHALT\n"), []);
+    }
+
+    #[test]
+    fn entry() {
+        // Reset runs from address 0, and slides over a fill of `NOP`s.
+        let at = |layout: &str| {
+            let program = assemble_with(&parse("IF Z\nHALT\n").unwrap(), &Layout::parse(layout).unwrap()).unwrap();
+            lint(&program).len()
+        };
+        assert_eq!((at(""), at("code=0x10"), at("code=0x10,fill=0xFF")), (1, 1, 0));
+    }
+
+    #[test]
+    fn skips() {
+        let guards = |src: &str| {
+            let warnings = warnings(&format!("TEST\n{src}"));
+            warnings.into_iter().filter(|(_, message)| message.contains("guards")).collect::<Vec<_>>()
+        };
+        assert_eq!(guards("IF Z\nSTATUS\n"), [(2, "`IF Z` guards `STATUS`, which runs whether or not it is skipped".into())]);
+        assert_eq!(
+            guards("IF Z\nIF NZ\nHALT\n"),
+            [(2, "`IF Z` guards `IF NZ`, which is tested whether or not it is skipped".into())]
+        );
+        assert_eq!(guards("IF Z\n.byte 0x01\n"), [(2, "`IF Z` guards data from line 3".into())]);
+        assert_eq!(guards("IF Z\nNOP\nIF Z\nPUSH\n"), []);
+        // In address order, every time.
+        let lines = guards("IF Z\nSTATUS\nIF Z\n.byte 0x01\nIF NZ\nSTATUS\n").into_iter().map(|(line, _)| line);
+        assert_eq!(lines.collect::<Vec<_>>(), [2, 4, 6]);
+    }
+}
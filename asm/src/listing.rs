@@ -0,0 +1,78 @@
+use std::io::{self, Write};
+
+use crate::assemble::Program;
+
+/// Writes a listing of `program` against the `source` it was assembled
+/// from.  Every source line is echoed with the address and bytes of the
+/// code it produced; any further instructions from the same line, such as a
+/// macro's expansion, follow it in their `Opcode` form.  Data is shown four
+/// bytes to a row, except that zero fill only gets its first row.  A
+/// summary of `CALL` encodings and a table of labels close the listing.
+pub fn write(out: &mut dyn Write, program: &Program, source: &str) -> io::Result<()> {
+    let mut items = program.items.iter().peekable();
+    let mut labels = program.labels.iter().peekable();
+
+    for (i, text) in source.lines().enumerate() {
+        let line = i + 1;
+        let text = text.trim_end();
+
+        let mut items_here = vec![];
+        while let Some(item) = items.next_if(|item| item.line == line) {
+            items_here.push(item);
+        }
+        let mut label = None;
+        while let Some(l) = labels.next_if(|l| l.line == line) {
+            label = label.or(Some(l));
+        }
+
+        // A macro use gets a line of its own, with its expansion beneath.
+        let expanded = items_here.iter().any(|item| item.expanded);
+        if items_here.is_empty() || expanded {
+            let addr = items_here.first().map(|item| item.addr).or(label.map(|l| l.addr));
+            let addr = addr.map_or(String::new(), |addr| format!("{addr:04X}"));
+            writeln!(out, "{addr:4}  {:<11}  {line:>5}  {text}", "")?;
+        }
+
+        for (i, item) in items_here.into_iter().enumerate() {
+            let mut rows = item.bytes.chunks(4).collect::<Vec<_>>();
+            if item.bytes.iter().all(|b| *b == 0) {
+                rows.truncate(1);
+            }
+            let text = match &item.op {
+                _ if i == 0 && !expanded => text.to_string(),
+                Some(op) => format!("  {op:?}"),
+                None => String::new(),
+            };
+            for (i, row) in rows.iter().enumerate() {
+                let addr = usize::from(item.addr) + 4 * i;
+                let row = format!("{addr:04X}  {:<11}  {line:>5}  {}", hex(row), if i == 0 { &text } else { "" });
+                writeln!(out, "{}", row.trim_end())?;
+            }
+        }
+    }
+
+    let calls = program.calls;
+    if calls.compact + calls.long > 0 {
+        writeln!(out)?;
+        writeln!(
+            out,
+            "Calls: {} compact, {} long; the compact form saved {} byte(s)",
+            calls.compact, calls.long, calls.compact
+        )?;
+    }
+
+    let mut labels = program.labels.iter().collect::<Vec<_>>();
+    labels.sort_by_key(|label| (label.addr, label.line));
+    if !labels.is_empty() {
+        writeln!(out)?;
+        writeln!(out, "Labels:")?;
+        for label in labels {
+            writeln!(out, "{:04X}  {}", label.addr, label.name)?;
+        }
+    }
+    Ok(())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02X}")).collect::<Vec<_>>().join(" ")
+}
@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+
+use crate::diag::Diagnostic;
+
+/// How deeply macros may expand inside one another before we assume the
+/// expansion is recursive.
+const MAX_DEPTH: usize = 32;
+
+/// A source line after macro expansion.
+pub struct Line {
+    pub line: usize,
+    pub text: String,
+    /// Whether the text came from a macro body rather than the source.
+    pub expanded: bool,
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<String>,
+}
+
+/// Collects `.macro name params...` / `.endm` definitions and replaces each
+/// use with its body.  Inside the body `\param` is replaced by the argument
+/// text and `\@` by `$` and a number unique to the expansion, so labels
+/// like `skip\@` clash neither between uses nor with the source's own
+/// labels, which cannot contain `$`.  Every expanded line keeps the line
+/// number of the outermost use.  A line that cannot be expanded is left out
+/// and reported, and expansion goes on.
+pub fn expand(src: &str) -> (Vec<Line>, Vec<Diagnostic>) {
+    let mut macros = HashMap::new();
+    let mut lines = vec![];
+    let mut errors = vec![];
+
+    let mut src_lines = src.lines().enumerate();
+    while let Some((i, text)) = src_lines.next() {
+        let line = i + 1;
+        let err = |message: String| Diagnostic::error(line, message);
+        let mut words = code(text).split_whitespace();
+        match words.next() {
+            Some(w) if w.eq_ignore_ascii_case(".macro") => {
+                let rest = words.collect::<Vec<_>>().join(" ");
+                let (name, params) = rest.split_once(char::is_whitespace).unwrap_or((&rest, ""));
+                if name.is_empty() {
+                    errors.push(err("`.macro` requires a name".into()));
+                }
+                let params = split_args(params).into_iter().filter(|p| !p.is_empty()).collect();
+                let mut body = vec![];
+                loop {
+                    let Some((j, text)) = src_lines.next() else {
+                        errors.push(err(format!("macro `{name}` is missing `.endm`")));
+                        break;
+                    };
+                    let first = code(text).split_whitespace().next().unwrap_or("");
+                    if first.eq_ignore_ascii_case(".endm") {
+                        break;
+                    }
+                    if first.eq_ignore_ascii_case(".macro") {
+                        errors.push(
+                            Diagnostic::error(j + 1, format!("macro `{name}` contains another `.macro`"))
+                                .note(format!("`{name}` starts on line {line}")),
+                        );
+                        continue;
+                    }
+                    if reserved(text) {
+                        errors.push(
+                            Diagnostic::error(j + 1, "unexpected character `$`")
+                                .note("`$` is kept for the labels `\\@` makes"),
+                        );
+                        continue;
+                    }
+                    body.push(text.to_string());
+                }
+                if macros.insert(name.to_ascii_uppercase(), Macro { params, body }).is_some() {
+                    errors.push(err(format!("macro `{name}` is defined more than once")));
+                }
+            }
+            Some(w) if w.eq_ignore_ascii_case(".endm") => errors.push(err("`.endm` without `.macro`".into())),
+            _ => lines.push((line, text)),
+        }
+    }
+
+    let mut expanded = vec![];
+    let mut count = 0;
+    for (line, text) in lines {
+        if let Err(diag) = expand_line(&macros, line, text, 0, &mut count, &mut expanded) {
+            errors.push(diag);
+        }
+    }
+    (expanded, errors)
+}
+
+fn expand_line(
+    macros: &HashMap<String, Macro>,
+    line: usize,
+    text: &str,
+    depth: usize,
+    count: &mut usize,
+    out: &mut Vec<Line>,
+) -> Result<(), Diagnostic> {
+    let code = code(text).trim();
+    let (label, rest) = match code.split_once(':') {
+        Some((label, rest)) if is_ident(label.trim()) => (Some(label.trim()), rest.trim()),
+        _ => (None, code),
+    };
+    let (name, args) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+
+    let Some(mac) = macros.get(&name.to_ascii_uppercase()) else {
+        out.push(Line { line, text: text.to_string(), expanded: depth > 0 });
+        return Ok(());
+    };
+
+    let err = |message: String| Diagnostic::error(line, message);
+    if depth >= MAX_DEPTH {
+        return Err(err(format!("macro `{name}` expands too deeply")));
+    }
+    let args = split_args(args);
+    let args = if args == [""] { vec![] } else { args };
+    if args.len() != mac.params.len() {
+        return Err(err(format!(
+            "macro `{name}` takes {} argument(s), found {}",
+            mac.params.len(),
+            args.len()
+        )));
+    }
+
+    if let Some(label) = label {
+        out.push(Line { line, text: format!("{label}:"), expanded: depth > 0 });
+    }
+    let unique = *count;
+    *count += 1;
+    for body in &mac.body {
+        let text = substitute(body, &mac.params, &args, unique);
+        expand_line(macros, line, &text, depth + 1, count, out)?;
+    }
+    Ok(())
+}
+
+fn substitute(text: &str, params: &[String], args: &[String], unique: usize) -> String {
+    let mut out = String::new();
+    let mut rest = text;
+    while let Some(i) = rest.find('\\') {
+        out.push_str(&rest[..i]);
+        let after = &rest[i + 1..];
+        let len = after.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(after.len());
+        if let Some(after) = after.strip_prefix('@') {
+            out.push_str(&format!("${unique}"));
+            rest = after;
+        } else if let Some(p) = params.iter().position(|p| *p == after[..len]) {
+            out.push_str(&args[p]);
+            rest = &after[len..];
+        } else {
+            // Not ours, most likely an escape in a string.
+            out.push('\\');
+            rest = after;
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Splits macro arguments on commas outside brackets and strings.
+fn split_args(text: &str) -> Vec<String> {
+    let mut args = vec![];
+    let mut current = String::new();
+    let mut depth = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    for c in text.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '[' | '(' if !quoted => depth += 1,
+            ']' | ')' if !quoted => depth -= 1,
+            ',' if !quoted && depth == 0 => {
+                args.push(current.trim().to_string());
+                current.clear();
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    args.push(current.trim().to_string());
+    args
+}
+
+/// Whether a line's code has a `$` outside strings.
+fn reserved(text: &str) -> bool {
+    let mut quoted = false;
+    let mut escaped = false;
+    for c in code(text).chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '$' if !quoted => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// The part of a line before any comment.
+fn code(text: &str) -> &str {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ';' if !quoted => return &text[..i],
+            _ => {}
+        }
+    }
+    text
+}
+
+fn is_ident(s: &str) -> bool {
+    s.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '.' || c == '$')
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '$')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble::assemble;
+    use crate::parse::parse;
+
+    const SKIP: &str = ".macro inc\n    IF NZ\n    BR skip\\@\n    ADD #1\nskip\\@:\n.endm\n";
+
+    #[test]
+    fn unique_labels() {
+        let (lines, errors) = expand(&format!("{SKIP}    inc\n    inc\n"));
+        assert!(errors.is_empty());
+        let labels = lines.iter().filter(|line| line.text.ends_with(':')).map(|line| line.text.as_str());
+        assert_eq!(labels.collect::<Vec<_>>(), ["skip$0:", "skip$1:"]);
+        // The source's own labels cannot clash with them.
+        let program = assemble(&parse(&format!("{SKIP}skip0:\n    inc\nskip1:\n    inc\n")).unwrap()).unwrap();
+        assert_eq!(program.labels.len(), 4);
+    }
+
+    #[test]
+    fn reserved_names() {
+        let (_, errors) = expand(".macro m\n    BR skip$0\n    .ascii \"$\"\n.endm\n");
+        assert_eq!(errors.iter().map(|diag| diag.line).collect::<Vec<_>>(), [2]);
+        let errors = parse("skip$0:\n").err().unwrap();
+        assert_eq!(errors.0[0].message, "unexpected character `$`");
+    }
+}
@@ -1,1078 +1,548 @@
-enum Opcode {
-    Text(u8),
-    Nop,
-    Halt,
-    Trap,
-    OutLo,
-    OutHi,
-    Push,
-    Pop,
-    Drop,
-    Return,
-    BranchIndirect,
-    CallIndirect,
-    Status,
-    CallWord(u16),
-    LoadImmediateWord(u16),
-    Not,
-    SetDataPointer,
-    Test,
-    LoadIndirect(RelativeTo, AddressingMode),
-    Load(Source),
-    Store(Source),
-    Add(Source),
-    Sub(Source),
-    And(Source),
-    Or(Source),
-    Xor(Source),
-    Shift(Direction, ShiftSource),
-    Branch(Target),
-    Call(Target),
-    If(Condition),
+use std::io::Write;
+
+use asm::output::{self, Format, Padding};
+use asm::debug::DebugInfo;
+use asm::diag::Diagnostics;
+use asm::object::Object;
+use asm::{assemble, cfg, disasm, link, lint, listing, parse, sim, stack, Opcode};
+
+const USAGE: &str = "\
+usage: asm <command> [options] <input>
+       asm link [options] <input>...
+       asm cfg|stack [options] <input>...
+
+commands:
+  assemble   assemble a source file into a memory image
+  link       link objects (`.o`) and sources into a memory image; the
+             first input's code comes first
+  disasm     disassemble a memory image
+  cfg        write the control-flow graph of a program as Graphviz DOT;
+             several inputs are linked first
+  stack      report how deep each subroutine of a program takes the stack,
+             and any path that leaves it unbalanced
+  run        run a program on the simulator
+  dump       print a hex dump of a memory image
+
+options:
+  -o, --output <path>        write to <path> instead of the default
+                             (`<input>.mem` for assemble and link, stdout
+                             otherwise)
+  -c, --object               assemble to a relocatable object (`<input>.o`)
+                             for link
+  -f, --format <format>      memory image format for assemble: readmemh,
+                             readmemh-bytes, bin, ihex or spi-ram-emu
+      --pad <size>           pad the image to `full` (64 KiB), `none`, or a
+                             byte count (default: full for readmemh, else none)
+      --layout <spec>        place sections, e.g. `code=0,data=0x400,bss=0x800,
+                             stack=0x10000,stack-size=0x100` (data and bss
+                             follow the section before them by default)
+      --fill <byte>          fill gaps between sections with <byte>
+  -l, --listing <path>       write the assembly listing to <path> (default:
+                             `<output>.lst` when assembling source to a file)
+      --no-listing           do not write a listing
+      --symbols <path>       write the labels and an address-to-source-line
+                             map of an assembled or linked image as JSON
+      --input-format <fmt>   read the input as `asm` source or as an image in
+                             any format above (default: by file extension)
+      --data-in <byte>       value on the data input pins for run
+      --max-steps <n>        stop run after <n> instructions (default 1000000)
+      --trace                print every instruction executed by run
+      --width <n>            bytes per line for dump (default 16)
+
+Use `-` as the input or output path for stdin or stdout.";
+
+/// Where the CPU starts after reset, wherever the layout places `.text`.
+const RESET: u16 = 0;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Command {
+    Assemble,
+    Link,
+    Disasm,
+    Cfg,
+    Stack,
+    Run,
+    Dump,
 }
 
-impl Opcode {
-    fn encode(&self) -> Encoded {
-        match self {
-            Opcode::Text(v) => Encoded::U8(*v),
-            Opcode::Nop => Encoded::U8(0x00),
-            Opcode::Halt => Encoded::U8(0x01),
-            Opcode::Trap => Encoded::U8(0x02),
-            Opcode::Drop => Encoded::U8(0x03),
-            Opcode::Push => Encoded::U8(0x04),
-            Opcode::Pop => Encoded::U8(0x05),
-            Opcode::Return => Encoded::U8(0x06),
-            Opcode::Not => Encoded::U8(0x07),
-            Opcode::OutLo => Encoded::U8(0x08),
-            Opcode::OutHi => Encoded::U8(0x09),
-            Opcode::SetDataPointer => Encoded::U8(0x0A),
-            Opcode::Test => Encoded::U8(0x0B),
-            Opcode::BranchIndirect => Encoded::U8(0x0C),
-            Opcode::CallIndirect => Encoded::U8(0x0D),
-            Opcode::Status => Encoded::U8(0x10),
-            Opcode::CallWord(w) => Encoded::U8U16(0x3E, *w),
-            Opcode::LoadImmediateWord(w) => Encoded::U8U16(0x3F, *w),
-            Opcode::LoadIndirect(r, m) => Encoded::U8(0x44 | r.encode() | m.encode()),
-            Opcode::Load(s) => s.encode(0x80),
-            Opcode::Store(s) => s.encode(0x90),
-            Opcode::Add(s) => s.encode(0x88),
-            Opcode::Sub(s) => s.encode(0x98),
-            Opcode::And(s) => s.encode(0xA0),
-            Opcode::Or(s) => s.encode(0xA8),
-            Opcode::Xor(s) => s.encode(0xB0),
-            Opcode::Shift(d, s) => s.encode(0xB8, *d),
-            Opcode::Branch(t) => t.encode(0xC0),
-            Opcode::Call(t) => t.encode(0xD0),
-            Opcode::If(c) => c.encode(0xF0),
-        }
-    }
+#[derive(Clone, Copy)]
+enum InputFormat {
+    Asm,
+    Image(Format),
 }
 
-enum Source {
-    Const(ByteInWord, u8),
-    Data(ByteInWord),
-    Ram(RelativeTo, AddressingMode, u8),
+struct Options {
+    command: Command,
+    input: String,
+    /// Every input, of which `link` takes several.
+    inputs: Vec<String>,
+    output: Option<String>,
+    object: bool,
+    input_format: Option<InputFormat>,
+    format: Format,
+    padding: Option<Padding>,
+    layout: assemble::Layout,
+    listing: Option<String>,
+    no_listing: bool,
+    symbols: Option<String>,
+    data: u8,
+    max_steps: usize,
+    trace: bool,
+    width: usize,
 }
 
-impl Source {
-    fn encode(&self, op: u8) -> Encoded {
-        let mut res = u16::from(op) << 8;
-        res |= match self {
-            Source::Const(b, c) => b.encode() | u16::from(*c),
-            Source::Data(b) => 0x0200 | b.encode(),
-            Source::Ram(r, m, a) => {
-                let opcode = 0x0400;
-                let relative = u16::from(r.encode()) << 8;
-                let mode = u16::from(m.encode()) << 8;
-                let addr = u16::from(*a);
-                opcode | relative | mode | addr
-            }
-        };
-        Encoded::U16(res)
+fn main() {
+    let opts = match parse_args(std::env::args().skip(1)) {
+        Ok(opts) => opts,
+        Err(e) => {
+            eprintln!("asm: {e}\n\n{USAGE}");
+            std::process::exit(2);
+        }
+    };
+
+    let res = match opts.command {
+        Command::Assemble => assemble_cmd(&opts),
+        Command::Link => link_cmd(&opts),
+        Command::Disasm => disasm_cmd(&opts),
+        Command::Cfg => cfg_cmd(&opts),
+        Command::Stack => stack_cmd(&opts),
+        Command::Run => run_cmd(&opts),
+        Command::Dump => dump_cmd(&opts),
+    };
+
+    if let Err(e) = res {
+        let origin = if opts.command == Command::Link || opts.inputs.len() > 1 { "link" } else { &opts.input };
+        for line in e.to_string().lines() {
+            eprintln!("{origin}: {line}");
+        }
+        std::process::exit(1);
     }
 }
 
-#[derive(Clone, Copy)]
-enum Direction {
-    Left,
-    Right,
-}
-
-impl Direction {
-    fn encode(&self) -> u16 {
-        match self {
-            Direction::Left => 0,
-            Direction::Right => 1,
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let command = match args.next().as_deref() {
+        Some("assemble" | "asm") => Command::Assemble,
+        Some("link") => Command::Link,
+        Some("disasm") => Command::Disasm,
+        Some("cfg") => Command::Cfg,
+        Some("stack") => Command::Stack,
+        Some("run") => Command::Run,
+        Some("dump") => Command::Dump,
+        Some("-h" | "--help" | "help") => {
+            println!("{USAGE}");
+            std::process::exit(0);
+        }
+        Some(other) => return Err(format!("unknown command `{other}`")),
+        None => return Err("missing command".into()),
+    };
+
+    let mut opts = Options {
+        command,
+        input: String::new(),
+        inputs: vec![],
+        output: None,
+        object: false,
+        input_format: None,
+        format: Format::Readmemh,
+        padding: None,
+        layout: assemble::Layout::default(),
+        listing: None,
+        no_listing: false,
+        symbols: None,
+        data: 0,
+        max_steps: 1_000_000,
+        trace: false,
+        width: 16,
+    };
+
+    // `--fill` wins over a `fill=` in `--layout`, whichever comes first.
+    let mut fill = None;
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or_else(|| format!("`{name}` requires a value"));
+        match arg.as_str() {
+            "-o" | "--output" => opts.output = Some(value(&arg)?),
+            "-c" | "--object" => opts.object = true,
+            "-f" | "--format" => {
+                let name = value(&arg)?;
+                opts.format = Format::from_name(&name).ok_or_else(|| format!("unknown format `{name}`"))?;
+            }
+            "--pad" => {
+                let name = value(&arg)?;
+                opts.padding = Some(Padding::from_name(&name).ok_or_else(|| format!("invalid padding `{name}`"))?);
+            }
+            "--layout" => opts.layout = assemble::Layout::parse(&value(&arg)?)?,
+            "--fill" => fill = Some(parse_int(&value(&arg)?)?),
+            "-l" | "--listing" => opts.listing = Some(value(&arg)?),
+            "--no-listing" => opts.no_listing = true,
+            "--symbols" => opts.symbols = Some(value(&arg)?),
+            "--input-format" => {
+                let name = value(&arg)?;
+                opts.input_format = Some(match name.as_str() {
+                    "asm" => InputFormat::Asm,
+                    _ => InputFormat::Image(
+                        Format::from_name(&name).ok_or_else(|| format!("unknown input format `{name}`"))?,
+                    ),
+                })
+            }
+            "--data-in" => opts.data = parse_int(&value(&arg)?)?,
+            "--max-steps" => opts.max_steps = parse_int(&value(&arg)?)?,
+            "--trace" => opts.trace = true,
+            "--width" => opts.width = parse_int::<usize>(&value(&arg)?)?.max(1),
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                std::process::exit(0);
+            }
+            _ if arg.starts_with('-') && arg != "-" => return Err(format!("unknown option `{arg}`")),
+            _ if opts.inputs.is_empty() || matches!(command, Command::Link | Command::Cfg | Command::Stack) => opts.inputs.push(arg),
+            _ => return Err(format!("unexpected argument `{arg}`")),
         }
     }
+
+    if let Some(fill) = fill {
+        opts.layout.fill = fill;
+    }
+    opts.input = opts.inputs.first().ok_or("missing input path")?.clone();
+    Ok(opts)
 }
 
-enum ShiftSource {
-    Const(u8),
-    Data,
-    Ram(RelativeTo, AddressingMode, u8),
+fn parse_int<T: TryFrom<u64>>(s: &str) -> Result<T, String> {
+    let n = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    n.ok()
+        .and_then(|n| T::try_from(n).ok())
+        .ok_or_else(|| format!("invalid number `{s}`"))
 }
 
-impl ShiftSource {
-    fn encode(&self, op: u8, d: Direction) -> Encoded {
-        let mut res = u16::from(op) << 8;
-        res |= match self {
-            ShiftSource::Const(c) => (d.encode() << 8) | u16::from(*c),
-            ShiftSource::Data => 0x0200 | d.encode(),
-            ShiftSource::Ram(r, m, a) => {
-                let opcode = 0x0400;
-                let relative = u16::from(r.encode()) << 8;
-                let mode = u16::from(m.encode()) << 8;
-                let addr = 0xFFFE & u16::from(*a);
-                opcode | relative | mode | addr | d.encode()
-            }
-        };
-        Encoded::U16(res)
+type CmdResult = Result<(), Box<dyn std::error::Error>>;
+
+fn read_input(path: &str) -> std::io::Result<Vec<u8>> {
+    if path == "-" {
+        let mut data = vec![];
+        std::io::Read::read_to_end(&mut std::io::stdin(), &mut data)?;
+        Ok(data)
+    } else {
+        std::fs::read(path)
     }
 }
 
-enum RelativeTo {
-    DataPointer,
-    StackPointer,
+fn open_output(path: Option<&str>) -> std::io::Result<Box<dyn std::io::Write>> {
+    match path {
+        None | Some("-") => Ok(Box::new(std::io::stdout().lock())),
+        Some(path) => Ok(Box::new(std::io::BufWriter::new(std::fs::File::create(path)?))),
+    }
 }
 
-impl RelativeTo {
-    fn encode(&self) -> u8 {
-        match self {
-            RelativeTo::DataPointer => 0,
-            RelativeTo::StackPointer => 2,
-        }
+/// Parses `source` and hands the statements to `assemble`, even past lines
+/// that did not parse, so that one run finds every problem.  They are
+/// printed against the source, and the error returned only counts them.
+fn assemble_source<T>(
+    file: &str,
+    source: &str,
+    assemble: impl FnOnce(&[parse::Statement]) -> Result<T, Diagnostics>,
+) -> Result<T, Box<dyn std::error::Error>> {
+    let (stmts, mut errors) = parse::parse_recovering(source);
+    match assemble(&stmts) {
+        Ok(res) if errors.is_empty() => return Ok(res),
+        Ok(_) => {}
+        Err(Diagnostics(more)) => errors.extend(more),
     }
+    errors.sort_by_key(|diag| diag.line);
+    Diagnostics(errors).render(&mut std::io::stderr().lock(), file, source)?;
+    Err("could not assemble".into())
+}
+
+/// A memory image, along with the program and source text it was assembled
+/// from when the input was assembly.
+struct Loaded {
+    bytes: Vec<u8>,
+    program: Option<(assemble::Program, String)>,
 }
 
-enum AddressingMode {
-    Direct,
-    Indirect,
+fn load_image(opts: &Options) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    Ok(load(opts)?.bytes)
 }
 
-impl AddressingMode {
-    fn encode(&self) -> u8 {
-        match self {
-            AddressingMode::Direct => 0,
-            AddressingMode::Indirect => 1,
+fn load(opts: &Options) -> Result<Loaded, Box<dyn std::error::Error>> {
+    let data = read_input(&opts.input)?;
+    let extension = std::path::Path::new(&opts.input).extension().and_then(|e| e.to_str());
+    let format = opts.input_format.unwrap_or(match extension {
+        Some("mem") => InputFormat::Image(Format::Readmemh),
+        Some("bin") => InputFormat::Image(Format::Binary),
+        Some("hex" | "ihex") => InputFormat::Image(Format::IntelHex),
+        _ => InputFormat::Asm,
+    });
+    match format {
+        InputFormat::Image(format) => Ok(Loaded { bytes: output::read(format, &data)?, program: None }),
+        InputFormat::Asm => {
+            let text = String::from_utf8(data)?;
+            let program = assemble_source(&opts.input, &text, |stmts| assemble::assemble_with(stmts, &opts.layout))?;
+            Diagnostics(lint::lint(&program)).render(&mut std::io::stderr().lock(), &opts.input, &text)?;
+            Ok(Loaded { bytes: program.image(), program: Some((program, text)) })
         }
     }
 }
 
-enum ByteInWord {
-    Lo,
-    Hi,
+/// Where to write the output: `-o`, or the first input with `extension`.
+fn output_path(opts: &Options, extension: &str) -> String {
+    match &opts.output {
+        Some(path) => path.clone(),
+        None if opts.input == "-" => "-".into(),
+        None => std::path::Path::new(&opts.input).with_extension(extension).to_string_lossy().into_owned(),
+    }
 }
 
-impl ByteInWord {
-    fn encode(&self) -> u16 {
-        match self {
-            ByteInWord::Lo => 0x0000,
-            ByteInWord::Hi => 0x0100,
-        }
-    }
+fn write_image(opts: &Options, output: &str, bytes: &[u8]) -> CmdResult {
+    let padding = opts.padding.unwrap_or(opts.format.default_padding());
+    let mut out = open_output(Some(output))?;
+    output::write(&mut out, opts.format, padding, bytes)?;
+    out.flush()?;
+    Ok(())
 }
 
-enum Target {
-    I11(i16),
-    U11(u16),
+fn write_symbols(path: &str, debug: &DebugInfo) -> CmdResult {
+    let mut out = open_output(Some(path))?;
+    debug.write_json(&mut out)?;
+    out.flush()?;
+    Ok(())
 }
 
-impl Target {
-    fn encode(&self, op: u8) -> Encoded {
-        let mut res = u16::from(op) << 8;
-        res |= match self {
-            Target::I11(v) => (*v as u16) & 0x07FF,
-            Target::U11(v) => v & 0x07FF,
-        };
-        Encoded::U16(res)
+fn assemble_cmd(opts: &Options) -> CmdResult {
+    if opts.object {
+        let text = String::from_utf8(read_input(&opts.input)?)?;
+        let object = assemble_source(&opts.input, &text, assemble::assemble_object)?;
+        let object = Object { source: Some(opts.input.clone()), ..object };
+        let mut out = open_output(Some(&output_path(opts, "o")))?;
+        object.write(&mut out)?;
+        out.flush()?;
+        return Ok(());
+    }
+
+    let loaded = load(opts)?;
+    let output = output_path(opts, opts.format.extension());
+    write_image(opts, &output, &loaded.bytes)?;
+
+    let listing = match &opts.listing {
+        _ if opts.no_listing => None,
+        Some(path) => Some(path.clone()),
+        None if output == "-" => None,
+        None => Some(std::path::Path::new(&output).with_extension("lst").to_string_lossy().into_owned()),
+    };
+    if let (Some(path), Some((program, source))) = (listing, &loaded.program) {
+        let mut out = open_output(Some(&path))?;
+        listing::write(&mut out, program, source)?;
+        out.flush()?;
+    }
+    if let (Some(path), Some((program, _))) = (&opts.symbols, &loaded.program) {
+        write_symbols(path, &DebugInfo::from_program(program, &opts.input))?;
     }
+    Ok(())
 }
 
-enum Condition {
-    Zero,
-    NotZero,
-    Else,
-    NotElse,
-    Negative,
-    NotNegative,
-    Carry,
-    NotCarry,
+fn link_cmd(opts: &Options) -> CmdResult {
+    let linked = link_inputs(opts)?;
+    write_image(opts, &output_path(opts, opts.format.extension()), &linked.image)?;
+    if let Some(path) = &opts.symbols {
+        write_symbols(path, &linked.debug)?;
+    }
+    Ok(())
 }
 
-impl Condition {
-    fn encode(&self, op: u8) -> Encoded {
-        let mut res = u16::from(op) << 8;
-        res |= match self {
-            Condition::Zero        => 0x0000,
-            Condition::NotZero     => 0x0001,
-            Condition::Else        => 0x0002,
-            Condition::NotElse     => 0x0003,
-            Condition::Negative    => 0x0004,
-            Condition::NotNegative => 0x0005,
-            Condition::Carry       => 0x0006,
-            Condition::NotCarry    => 0x0007,
+/// Links every input, reading `.o` files as objects and assembling the
+/// rest.
+fn link_inputs(opts: &Options) -> Result<link::Linked, Box<dyn std::error::Error>> {
+    let mut objects = vec![];
+    for path in &opts.inputs {
+        let text = String::from_utf8(read_input(path)?)?;
+        let object = if path.ends_with(".o") {
+            Object::read(&text).map_err(|e| format!("{path}: {e}"))?
+        } else {
+            let object = assemble_source(path, &text, assemble::assemble_object)?;
+            Object { source: Some(path.clone()), ..object }
         };
-        Encoded::U16(res)
+        objects.push((path.clone(), object));
     }
+    Ok(link::link(&objects, &opts.layout)?)
 }
 
-enum Encoded {
-    U16(u16),
-    U8(u8),
-    U8U16(u8, u16),
+fn disasm_cmd(opts: &Options) -> CmdResult {
+    let bytes = load_image(opts)?;
+    let mut out = open_output(opts.output.as_deref())?;
+    for line in disasm::disassemble(&bytes) {
+        writeln!(out, "{line}")?;
+    }
+    out.flush()?;
+    Ok(())
 }
 
-fn main() {
-    let ops_insts = [
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Load(Source::Const(ByteInWord::Lo, 0x14)),
-        Opcode::Add(Source::Const(ByteInWord::Lo, 0x1e)),
-        Opcode::OutLo,
-        Opcode::Nop,
-        Opcode::Load(Source::Data(ByteInWord::Lo)),
-        Opcode::Add(Source::Const(ByteInWord::Lo, 0x1e)),
-        Opcode::OutLo,
-        Opcode::Nop,
-        Opcode::Load(Source::Ram(RelativeTo::DataPointer, AddressingMode::Direct, 0x20)),
-        Opcode::Add(Source::Ram(RelativeTo::DataPointer, AddressingMode::Direct, 0x22)),
-        Opcode::OutLo,
-        Opcode::Nop,
-        Opcode::Branch(Target::I11(0x0010)),
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Text(0x00),
-        Opcode::Text(0x14),
-        Opcode::Text(0x00),
-        Opcode::Text(0x1e),
-        Opcode::Load(Source::Const(ByteInWord::Lo, 0x5A)),
-        Opcode::Branch(Target::I11(0x0008)),
-        Opcode::Load(Source::Const(ByteInWord::Lo, 0xA5)),
-        Opcode::OutLo,
-        Opcode::Nop,
-        Opcode::Load(Source::Const(ByteInWord::Lo, 0x00)),
-        Opcode::Branch(Target::I11(0x07F4)),
-        Opcode::OutLo,
-        Opcode::Nop,
-        Opcode::Load(Source::Data(ByteInWord::Lo)),
-        Opcode::Test,
-        Opcode::If(Condition::NotZero),
-        Opcode::Branch(Target::I11(0x07F7)),
-        Opcode::Load(Source::Const(ByteInWord::Lo, 0x09)),
-        Opcode::Store(Source::Ram(RelativeTo::DataPointer, AddressingMode::Direct, 0x20)),
-        Opcode::Load(Source::Const(ByteInWord::Lo, 0x33)),
-        Opcode::Load(Source::Ram(RelativeTo::DataPointer, AddressingMode::Direct, 0x20)),
-        Opcode::Add(Source::Ram(RelativeTo::DataPointer, AddressingMode::Direct, 0x22)),
-        Opcode::OutLo,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Load(Source::Const(ByteInWord::Lo, 0xFF)),
-        Opcode::Sub(Source::Const(ByteInWord::Lo, 0xEE)),
-        Opcode::OutLo,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Load(Source::Const(ByteInWord::Lo, 0xF0)),
-        Opcode::And(Source::Const(ByteInWord::Lo, 0x3C)),
-        Opcode::OutLo,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Load(Source::Const(ByteInWord::Lo, 0xF0)),
-        Opcode::Or(Source::Const(ByteInWord::Lo, 0x3C)),
-        Opcode::OutLo,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Load(Source::Const(ByteInWord::Lo, 0xF0)),
-        Opcode::Xor(Source::Const(ByteInWord::Lo, 0x3C)),
-        Opcode::OutLo,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Load(Source::Const(ByteInWord::Lo, 0xA5)),
-        Opcode::Not,
-        Opcode::OutLo,
-        Opcode::Load(Source::Const(ByteInWord::Lo, 0x20)),
-        Opcode::LoadIndirect(RelativeTo::DataPointer, AddressingMode::Direct),
-        Opcode::OutLo,
-        Opcode::Load(Source::Const(ByteInWord::Lo, 0x22)),
-        Opcode::Store(Source::Ram(RelativeTo::DataPointer, AddressingMode::Direct, 0x1E)),
-        Opcode::Load(Source::Const(ByteInWord::Lo, 0)),
-        Opcode::Load(Source::Ram(RelativeTo::DataPointer, AddressingMode::Indirect, 0x1E)),
-        Opcode::OutLo,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Load(Source::Const(ByteInWord::Lo, 0x99)),
-        Opcode::Push,
-        Opcode::Nop,
-        Opcode::Load(Source::Const(ByteInWord::Lo, 0x00)),
-        Opcode::Load(Source::Ram(RelativeTo::StackPointer, AddressingMode::Direct, 0x00)),
-        Opcode::OutLo,
-        Opcode::Nop,
-        Opcode::Load(Source::Const(ByteInWord::Lo, 0x00)),
-        Opcode::Pop,
-        Opcode::Nop,
-        Opcode::Add(Source::Const(ByteInWord::Lo, 0x11)),
-        Opcode::OutLo,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        // 0x90
-        Opcode::Call(Target::U11(0x98)),
-        Opcode::OutLo,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Branch(Target::I11(4)),
-        // callee 0x98
-        Opcode::Load(Source::Const(ByteInWord::Lo, 0x42)),
-        Opcode::Return,
-        Opcode::Nop,
-        // after callee 0x9C
-        Opcode::Load(Source::Const(ByteInWord::Lo, 0x22)),
-        Opcode::Or(Source::Const(ByteInWord::Hi, 0x33)),
-        Opcode::OutLo,
-        Opcode::OutHi,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Trap,
-        Opcode::Nop,
-        Opcode::Load(Source::Const(ByteInWord::Lo, 0)),
-        Opcode::Test,
-        Opcode::If(Condition::NotZero),
-        Opcode::Load(Source::Const(ByteInWord::Lo, 0xBD)),
-        Opcode::If(Condition::Else),
-        Opcode::Load(Source::Const(ByteInWord::Lo, 0xA4)),
-        Opcode::OutLo,
-        Opcode::Load(Source::Const(ByteInWord::Lo, 0)),
-        Opcode::Test,
-        Opcode::If(Condition::Zero),
-        Opcode::Load(Source::Const(ByteInWord::Lo, 0xBD)),
-        Opcode::If(Condition::NotElse),
-        Opcode::Load(Source::Const(ByteInWord::Lo, 0xA5)),
-        Opcode::OutLo,
-        Opcode::Load(Source::Const(ByteInWord::Lo, 2)),
-        Opcode::Store(Source::Ram(RelativeTo::DataPointer, AddressingMode::Direct, 0x1E)),
-        Opcode::Load(Source::Const(ByteInWord::Lo, 1)),
-        Opcode::OutLo,
-        Opcode::Shift(Direction::Left, ShiftSource::Const(1)),
-        Opcode::OutLo,
-        Opcode::Shift(Direction::Left, ShiftSource::Const(1)),
-        Opcode::OutLo,
-        Opcode::Shift(Direction::Left, ShiftSource::Const(1)),
-        Opcode::OutLo,
-        Opcode::Shift(Direction::Left, ShiftSource::Const(1)),
-        Opcode::OutLo,
-        Opcode::Shift(Direction::Left, ShiftSource::Const(1)),
-        Opcode::OutLo,
-        Opcode::Shift(Direction::Left, ShiftSource::Const(1)),
-        Opcode::OutLo,
-        Opcode::Shift(Direction::Left, ShiftSource::Const(1)),
-        Opcode::OutLo,
-        Opcode::Shift(Direction::Right, ShiftSource::Ram(RelativeTo::DataPointer, AddressingMode::Direct, 0x1E)),
-        Opcode::OutLo,
-        Opcode::Shift(Direction::Right, ShiftSource::Ram(RelativeTo::DataPointer, AddressingMode::Direct, 0x1E)),
-        Opcode::OutLo,
-        Opcode::Shift(Direction::Right, ShiftSource::Ram(RelativeTo::DataPointer, AddressingMode::Direct, 0x1E)),
-        Opcode::OutLo,
-        Opcode::Shift(Direction::Right, ShiftSource::Const(1)),
-        Opcode::OutLo,
-        Opcode::Shift(Direction::Right, ShiftSource::Const(1)),
-        Opcode::OutLo,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Load(Source::Const(ByteInWord::Lo, 2)),
-        Opcode::BranchIndirect,
-        Opcode::Load(Source::Const(ByteInWord::Lo, 3)),
-        Opcode::OutLo,
-        Opcode::Load(Source::Const(ByteInWord::Lo, 0x98)),
-        Opcode::CallIndirect,
-        Opcode::OutLo,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::LoadImmediateWord(0xABCD),
-        Opcode::OutHi,
-        Opcode::OutLo,
-        Opcode::CallWord(0x0098),
-        Opcode::OutLo,
-        Opcode::LoadImmediateWord(0xFFFF),
-        Opcode::Test,
-        Opcode::If(Condition::Negative),
-        Opcode::Load(Source::Const(ByteInWord::Lo, 0x72)),
-        Opcode::OutLo,
-        Opcode::LoadImmediateWord(0xFFFF),
-        Opcode::Test,
-        Opcode::If(Condition::NotNegative),
-        Opcode::Load(Source::Const(ByteInWord::Lo, 0x72)),
-        Opcode::OutLo,
-    ];
-
-    let target = 0x50;
-    let current = 0x52;
-    let cursor = 0x54;
-    let cache = 0x58;
-
-    let br0 = 0x13;
-    let br1 = 0x19;
-    let loop_label = 0x1D;
-    let br2 = 0x3D;
-    let br3 = 0x45;
-    let done = 0x45;
-
-    let fib_memo_insts = [
-        Opcode::Load(Source::Data(ByteInWord::Lo)),
-        Opcode::Store(Source::Ram(RelativeTo::DataPointer, AddressingMode::Direct, target)),
-        Opcode::Load(Source::Const(ByteInWord::Lo, 1)),
-        Opcode::Store(Source::Ram(RelativeTo::DataPointer, AddressingMode::Direct, cache)),
-        Opcode::Load(Source::Const(ByteInWord::Lo, 1)),
-        Opcode::Store(Source::Ram(RelativeTo::DataPointer, AddressingMode::Direct, cache + 2)),
-        Opcode::Load(Source::Ram(RelativeTo::DataPointer, AddressingMode::Direct, target)),
-        Opcode::Test,
-        Opcode::If(Condition::Zero),
-        Opcode::Branch(Target::I11(done - br0)),
-        Opcode::Sub(Source::Const(ByteInWord::Lo, 1)),
-        Opcode::If(Condition::Zero),
-        Opcode::Branch(Target::I11(done - br1)),
-        Opcode::Load(Source::Const(ByteInWord::Lo, 2)),
-        Opcode::Store(Source::Ram(RelativeTo::DataPointer, AddressingMode::Direct, current)),
-        // loop
-        Opcode::Load(Source::Ram(RelativeTo::DataPointer, AddressingMode::Direct, current)),
-        Opcode::Add(Source::Ram(RelativeTo::DataPointer, AddressingMode::Direct, current)),
-        Opcode::Add(Source::Const(ByteInWord::Lo, cache)),
-        Opcode::Store(Source::Ram(RelativeTo::DataPointer, AddressingMode::Direct, cursor)),
-        Opcode::Sub(Source::Const(ByteInWord::Lo, 2)),
-        Opcode::LoadIndirect(RelativeTo::DataPointer, AddressingMode::Direct),
-        Opcode::Store(Source::Ram(RelativeTo::DataPointer, AddressingMode::Indirect, cursor)),
-        Opcode::Load(Source::Ram(RelativeTo::DataPointer, AddressingMode::Direct, cursor)),
-        Opcode::Sub(Source::Const(ByteInWord::Lo, 4)),
-        Opcode::LoadIndirect(RelativeTo::DataPointer, AddressingMode::Direct),
-        Opcode::Add(Source::Ram(RelativeTo::DataPointer, AddressingMode::Indirect, cursor)),
-        Opcode::Store(Source::Ram(RelativeTo::DataPointer, AddressingMode::Indirect, cursor)),
-        Opcode::OutLo,
-        Opcode::Nop,
-        Opcode::Load(Source::Ram(RelativeTo::DataPointer, AddressingMode::Direct, target)),
-        Opcode::Sub(Source::Ram(RelativeTo::DataPointer, AddressingMode::Direct, current)),
-        Opcode::If(Condition::Zero),
-        Opcode::Branch(Target::I11(done - br2)),
-        Opcode::Load(Source::Ram(RelativeTo::DataPointer, AddressingMode::Direct, current)),
-        Opcode::Add(Source::Const(ByteInWord::Lo, 1)),
-        Opcode::Store(Source::Ram(RelativeTo::DataPointer, AddressingMode::Direct, current)),
-        Opcode::Branch(Target::I11(loop_label - br3)),
-        // done
-        Opcode::Load(Source::Ram(RelativeTo::DataPointer, AddressingMode::Direct, target)),
-        Opcode::Add(Source::Ram(RelativeTo::DataPointer, AddressingMode::Direct, target)),
-        Opcode::Add(Source::Const(ByteInWord::Lo, cache)),
-        Opcode::LoadIndirect(RelativeTo::DataPointer, AddressingMode::Direct),
-        Opcode::OutLo,
-        Opcode::Halt,
-        // target
-        Opcode::Nop,
-        Opcode::Nop,
-        // current
-        Opcode::Nop,
-        Opcode::Nop,
-        // cursor
-        Opcode::Nop,
-        Opcode::Nop,
-        // cache
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-    ];
-
-    let target_abs = 0x60;
-    let target = 0x00;
-    let current = 0x02;
-    let cursor = 0x04;
-    let pointer = 0x06;
-    let cache = 0x08;
-
-    let br0 = 0x17;
-    let br1 = 0x1D;
-    let loop_label = 0x21;
-    let br2 = 0x47;
-    let br3 = 0x4F;
-    let done = 0x4F;
-
-    let fib_framed_insts = [
-        Opcode::Load(Source::Data(ByteInWord::Lo)),
-        Opcode::Store(Source::Ram(RelativeTo::DataPointer, AddressingMode::Direct, target_abs)),
-        Opcode::Load(Source::Const(ByteInWord::Lo, target_abs)),
-        Opcode::SetDataPointer,
-        Opcode::Nop,
-        Opcode::Load(Source::Const(ByteInWord::Lo, 1)),
-        Opcode::Store(Source::Ram(RelativeTo::DataPointer, AddressingMode::Direct, cache)),
-        Opcode::Load(Source::Const(ByteInWord::Lo, 1)),
-        Opcode::Store(Source::Ram(RelativeTo::DataPointer, AddressingMode::Direct, cache + 2)),
-        Opcode::Load(Source::Ram(RelativeTo::DataPointer, AddressingMode::Direct, target)),
-        Opcode::Test,
-        Opcode::If(Condition::Zero),
-        Opcode::Branch(Target::I11(done - br0)),
-        Opcode::Sub(Source::Const(ByteInWord::Lo, 1)),
-        Opcode::If(Condition::Zero),
-        Opcode::Branch(Target::I11(done - br1)),
-        Opcode::Load(Source::Const(ByteInWord::Lo, 2)),
-        Opcode::Store(Source::Ram(RelativeTo::DataPointer, AddressingMode::Direct, current)),
-        // loop
-        Opcode::Load(Source::Ram(RelativeTo::DataPointer, AddressingMode::Direct, current)),
-        Opcode::Add(Source::Ram(RelativeTo::DataPointer, AddressingMode::Direct, current)),
-        Opcode::Add(Source::Const(ByteInWord::Lo, cache)),
-        Opcode::Store(Source::Ram(RelativeTo::DataPointer, AddressingMode::Direct, cursor)),
-        Opcode::Add(Source::Const(ByteInWord::Lo, target_abs)),
-        Opcode::Store(Source::Ram(RelativeTo::DataPointer, AddressingMode::Direct, pointer)),
-        Opcode::Load(Source::Ram(RelativeTo::DataPointer, AddressingMode::Direct, cursor)),
-        Opcode::Sub(Source::Const(ByteInWord::Lo, 2)),
-        Opcode::LoadIndirect(RelativeTo::DataPointer, AddressingMode::Direct),
-        Opcode::Store(Source::Ram(RelativeTo::DataPointer, AddressingMode::Indirect, pointer)),
-        Opcode::Load(Source::Ram(RelativeTo::DataPointer, AddressingMode::Direct, cursor)),
-        Opcode::Sub(Source::Const(ByteInWord::Lo, 4)),
-        Opcode::LoadIndirect(RelativeTo::DataPointer, AddressingMode::Direct),
-        Opcode::Add(Source::Ram(RelativeTo::DataPointer, AddressingMode::Indirect, pointer)),
-        Opcode::Store(Source::Ram(RelativeTo::DataPointer, AddressingMode::Indirect, pointer)),
-        Opcode::OutLo,
-        Opcode::Nop,
-        Opcode::Load(Source::Ram(RelativeTo::DataPointer, AddressingMode::Direct, target)),
-        Opcode::Sub(Source::Ram(RelativeTo::DataPointer, AddressingMode::Direct, current)),
-        Opcode::If(Condition::Zero),
-        Opcode::Branch(Target::I11(done - br2)),
-        Opcode::Load(Source::Ram(RelativeTo::DataPointer, AddressingMode::Direct, current)),
-        Opcode::Add(Source::Const(ByteInWord::Lo, 1)),
-        Opcode::Store(Source::Ram(RelativeTo::DataPointer, AddressingMode::Direct, current)),
-        Opcode::Branch(Target::I11(loop_label - br3)),
-        // done
-        Opcode::Load(Source::Ram(RelativeTo::DataPointer, AddressingMode::Direct, target)),
-        Opcode::Add(Source::Ram(RelativeTo::DataPointer, AddressingMode::Direct, target)),
-        Opcode::Add(Source::Const(ByteInWord::Lo, cache)),
-        Opcode::LoadIndirect(RelativeTo::DataPointer, AddressingMode::Direct),
-        Opcode::OutLo,
-        Opcode::Halt,
-        // target
-        Opcode::Nop,
-        Opcode::Nop,
-        // current
-        Opcode::Nop,
-        Opcode::Nop,
-        // cursor
-        Opcode::Nop,
-        Opcode::Nop,
-        // cache
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-        Opcode::Nop,
-    ];
-
-    let fib_fn = 8;
-    let br1 = 15;
-    let br2 = 21;
-    let just_one = 38;
-
-    let fib_recursive_insts = [
-        Opcode::Load(Source::Data(ByteInWord::Lo)),
-        Opcode::Push,
-        Opcode::Nop,
-        Opcode::Call(Target::U11(fib_fn)),
-        Opcode::OutLo,
-        Opcode::Halt,
-        // fib_fn
-        Opcode::Load(Source::Ram(RelativeTo::StackPointer, AddressingMode::Direct, 2)),
-        Opcode::Test,
-        Opcode::If(Condition::Zero),
-        Opcode::Branch(Target::U11(just_one - br1)),
-        Opcode::Sub(Source::Const(ByteInWord::Lo, 1)),
-        Opcode::If(Condition::Zero),
-        Opcode::Branch(Target::U11(just_one - br2)),
-        Opcode::Push,
-        Opcode::Call(Target::U11(fib_fn)),
-        Opcode::Drop,
-        Opcode::Push,
-        Opcode::Load(Source::Ram(RelativeTo::StackPointer, AddressingMode::Direct, 4)),
-        Opcode::Sub(Source::Const(ByteInWord::Lo, 2)),
-        Opcode::Push,
-        Opcode::Call(Target::U11(fib_fn)),
-        Opcode::Drop,
-        Opcode::Add(Source::Ram(RelativeTo::StackPointer, AddressingMode::Direct, 0)),
-        Opcode::Drop,
-        Opcode::Return,
-        // just_one
-        Opcode::Load(Source::Const(ByteInWord::Lo, 1)),
-        Opcode::Return,
-        Opcode::Nop,
-    ];
-
-    run("ops.mem", &ops_insts).unwrap();
-    run("fib_memo.mem", &fib_memo_insts).unwrap();
-    run("fib_framed.mem", &fib_framed_insts).unwrap();
-    run("fib_recursive.mem", &fib_recursive_insts).unwrap();
-
-    run("op_halt.mem", &[
-        Opcode::Load(Source::Const(ByteInWord::Lo, 0)),
-        Opcode::Test,
-        Opcode::If(Condition::NotZero),
-        Opcode::Halt,
-        Opcode::Load(Source::Const(ByteInWord::Lo, 0)),
-        Opcode::Test,
-        Opcode::If(Condition::NotZero),
-        Opcode::Nop,
-        Opcode::If(Condition::NotElse),
-        Opcode::Halt,
-        Opcode::Load(Source::Const(ByteInWord::Lo, 0)),
-        Opcode::Test,
-        Opcode::If(Condition::Zero),
-        Opcode::Nop,
-        Opcode::If(Condition::Else),
-        Opcode::Halt,
-        Opcode::Load(Source::Const(ByteInWord::Lo, 1)),
-        Opcode::OutLo,
-        Opcode::Halt,
-    ]).unwrap();
-
-    run("op_trap.mem", &[
-        Opcode::Load(Source::Const(ByteInWord::Lo, 0)),
-        Opcode::Test,
-        Opcode::If(Condition::NotZero),
-        Opcode::Trap,
-        Opcode::Load(Source::Const(ByteInWord::Lo, 0)),
-        Opcode::Test,
-        Opcode::If(Condition::NotZero),
-        Opcode::Nop,
-        Opcode::If(Condition::NotElse),
-        Opcode::Trap,
-        Opcode::Load(Source::Const(ByteInWord::Lo, 0)),
-        Opcode::Test,
-        Opcode::If(Condition::Zero),
-        Opcode::Nop,
-        Opcode::If(Condition::Else),
-        Opcode::Trap,
-        Opcode::Load(Source::Const(ByteInWord::Lo, 1)),
-        Opcode::OutLo,
-        Opcode::Trap,
-        Opcode::Load(Source::Const(ByteInWord::Lo, 2)),
-        Opcode::OutLo,
-    ]).unwrap();
-
-    run("op_push.mem", &[
-        Opcode::Load(Source::Const(ByteInWord::Lo, 0x42)),
-        Opcode::Push,
-        Opcode::Load(Source::Const(ByteInWord::Lo, 1)),
-        Opcode::Test,
-        Opcode::If(Condition::Zero),
-        Opcode::Push,
-        Opcode::Load(Source::Const(ByteInWord::Lo, 2)),
-        Opcode::Test,
-        Opcode::If(Condition::Zero),
-        Opcode::Nop,
-        Opcode::If(Condition::NotElse),
-        Opcode::Push,
-        Opcode::Load(Source::Const(ByteInWord::Lo, 3)),
-        Opcode::Test,
-        Opcode::If(Condition::NotZero),
-        Opcode::Nop,
-        Opcode::If(Condition::Else),
-        Opcode::Push,
-        Opcode::Load(Source::Const(ByteInWord::Lo, 4)),
-        Opcode::Test,
-        Opcode::If(Condition::NotZero),
-        Opcode::Push,
-        Opcode::Load(Source::Const(ByteInWord::Lo, 0)),
-        Opcode::Pop,
-        Opcode::OutLo,
-        Opcode::Pop,
-        Opcode::OutLo,
-    ]).unwrap();
-
-    run("op_pop.mem", &[
-        Opcode::Load(Source::Const(ByteInWord::Lo, 5)),
-        Opcode::Push,
-        Opcode::Load(Source::Const(ByteInWord::Lo, 4)),
-        Opcode::Push,
-        Opcode::Load(Source::Const(ByteInWord::Lo, 3)),
-        Opcode::Push,
-        Opcode::Load(Source::Const(ByteInWord::Lo, 2)),
-        Opcode::Push,
-        Opcode::Load(Source::Const(ByteInWord::Lo, 1)),
-        Opcode::Push,
-        Opcode::Load(Source::Const(ByteInWord::Lo, 9)),
-        Opcode::If(Condition::Zero),
-        Opcode::Pop,
-        Opcode::Load(Source::Const(ByteInWord::Lo, 8)),
-        Opcode::Test,
-        Opcode::If(Condition::Zero),
-        Opcode::Nop,
-        Opcode::If(Condition::NotElse),
-        Opcode::Pop,
-        Opcode::Load(Source::Const(ByteInWord::Lo, 7)),
-        Opcode::Test,
-        Opcode::If(Condition::NotZero),
-        Opcode::Nop,
-        Opcode::If(Condition::Else),
-        Opcode::Pop,
-        Opcode::Pop,
-        Opcode::OutLo,
-        Opcode::Pop,
-        Opcode::OutLo,
-        Opcode::Pop,
-        Opcode::OutLo,
-        Opcode::Pop,
-        Opcode::OutLo,
-        Opcode::Pop,
-        Opcode::OutLo,
-    ]).unwrap();
-
-    run("op_drop.mem", &[
-        Opcode::Load(Source::Const(ByteInWord::Lo, 5)),
-        Opcode::Push,
-        Opcode::Load(Source::Const(ByteInWord::Lo, 4)),
-        Opcode::Push,
-        Opcode::Load(Source::Const(ByteInWord::Lo, 3)),
-        Opcode::Push,
-        Opcode::Load(Source::Const(ByteInWord::Lo, 2)),
-        Opcode::Push,
-        Opcode::Load(Source::Const(ByteInWord::Lo, 1)),
-        Opcode::Push,
-        Opcode::Load(Source::Const(ByteInWord::Lo, 9)),
-        Opcode::Test,
-        Opcode::If(Condition::Zero),
-        Opcode::Drop,
-        Opcode::Load(Source::Const(ByteInWord::Lo, 8)),
-        Opcode::Test,
-        Opcode::If(Condition::Zero),
-        Opcode::Nop,
-        Opcode::If(Condition::NotElse),
-        Opcode::Drop,
-        Opcode::Load(Source::Const(ByteInWord::Lo, 7)),
-        Opcode::Test,
-        Opcode::If(Condition::NotZero),
-        Opcode::Nop,
-        Opcode::If(Condition::Else),
-        Opcode::Drop,
-        Opcode::Pop,
-        Opcode::OutLo,
-        Opcode::Drop,
-        Opcode::OutLo,
-        Opcode::Pop,
-        Opcode::OutLo,
-        Opcode::Drop,
-        Opcode::OutLo,
-        Opcode::Pop,
-        Opcode::OutLo,
-    ]).unwrap();
-
-    run("op_test.mem", &[
-        Opcode::Load(Source::Const(ByteInWord::Lo, 0xFF)),
-        Opcode::OutLo,
-        Opcode::LoadImmediateWord(0x0000),
-        Opcode::Test,
-        Opcode::If(Condition::Zero),
-        Opcode::OutLo,
-        Opcode::LoadImmediateWord(0x0001),
-        Opcode::Test,
-        Opcode::If(Condition::Zero),
-        Opcode::OutLo,
-        Opcode::LoadImmediateWord(0x0001),
-        Opcode::Test,
-        Opcode::If(Condition::NotZero),
-        Opcode::OutLo,
-        Opcode::LoadImmediateWord(0x0000),
-        Opcode::Test,
-        Opcode::If(Condition::NotZero),
-        Opcode::OutLo,
-    ]).unwrap();
-
-    run("fault.mem", &[
-        Opcode::Store(Source::Const(ByteInWord::Lo, 0)),
-    ]).unwrap();
-
-    run("op_status.mem", &[
-        Opcode::Load(Source::Const(ByteInWord::Lo, 0)),
-        Opcode::Test,
-        Opcode::Status,
-        Opcode::Sub(Source::Const(ByteInWord::Lo, 0x01)),
-        Opcode::If(Condition::NotZero),
-        Opcode::Trap,
-        Opcode::Load(Source::Const(ByteInWord::Lo, 1)),
-        Opcode::Test,
-        Opcode::Status,
-        Opcode::Sub(Source::Const(ByteInWord::Lo, 0x00)),
-        Opcode::If(Condition::NotZero),
-        Opcode::Trap,
-        Opcode::LoadImmediateWord(0xFFFF),
-        Opcode::Test,
-        Opcode::Status,
-        Opcode::Sub(Source::Const(ByteInWord::Lo, 0x02)),
-        Opcode::If(Condition::NotZero),
-        Opcode::Trap,
-        Opcode::Status,
-        Opcode::Sub(Source::Const(ByteInWord::Lo, 0x21)),
-        Opcode::If(Condition::NotZero),
-        Opcode::Trap,
-        Opcode::Load(Source::Const(ByteInWord::Lo, 0)),
-        Opcode::Test,
-        Opcode::If(Condition::Zero),
-        Opcode::Nop,
-        Opcode::Status,
-        Opcode::Sub(Source::Const(ByteInWord::Lo, 0x01)),
-        Opcode::If(Condition::NotZero),
-        Opcode::Trap,
-        Opcode::Load(Source::Const(ByteInWord::Lo, 1)),
-        Opcode::OutLo,
-    ]).unwrap();
-
-    run("op_add_carry.mem", &[
-        // FFFE + 1 => no carry
-        Opcode::LoadImmediateWord(0xFFFE),
-        Opcode::Add(Source::Const(ByteInWord::Lo, 1)),
-        // assert no carry
-        Opcode::Status,
-        Opcode::And(Source::Const(ByteInWord::Lo, 0x04)),
-        Opcode::If(Condition::NotZero),
-        Opcode::Trap,
-        // FFFF + 1 => carry
-        Opcode::LoadImmediateWord(0xFFFF),
-        Opcode::Add(Source::Const(ByteInWord::Lo, 1)),
-        // assert carry
-        Opcode::Status,
-        Opcode::And(Source::Const(ByteInWord::Lo, 0x04)),
-        Opcode::If(Condition::Zero),
-        Opcode::Trap,
-        // 1 + FFFF => carry
-        Opcode::LoadImmediateWord(0xFFFF),
-        Opcode::Store(Source::Ram(RelativeTo::DataPointer, AddressingMode::Direct, 0xF0)),
-        Opcode::Load(Source::Const(ByteInWord::Lo, 1)),
-        Opcode::Add(Source::Ram(RelativeTo::DataPointer, AddressingMode::Direct, 0xF0)),
-        // assert carry
-        Opcode::Status,
-        Opcode::And(Source::Const(ByteInWord::Lo, 0x04)),
-        Opcode::If(Condition::Zero),
-        Opcode::Trap,
-        // FFFF + FF00 => carry
-        Opcode::LoadImmediateWord(0xFFFE),
-        Opcode::Add(Source::Const(ByteInWord::Hi, 0xFF)),
-        // assert carry
-        Opcode::Status,
-        Opcode::And(Source::Const(ByteInWord::Lo, 0x04)),
-        Opcode::If(Condition::Zero),
-        Opcode::Trap,
-        // done
-        Opcode::Load(Source::Const(ByteInWord::Lo, 1)),
-        Opcode::OutLo,
-    ]).unwrap();
-
-    run("op_sub_carry.mem", &[
-        // 1 - 1 => no carry
-        Opcode::Load(Source::Const(ByteInWord::Lo, 1)),
-        Opcode::Sub(Source::Const(ByteInWord::Lo, 1)),
-        // assert no carry
-        Opcode::Status,
-        Opcode::And(Source::Const(ByteInWord::Lo, 0x04)),
-        Opcode::If(Condition::NotZero),
-        Opcode::Trap,
-        // 0 - 1 => carry
-        Opcode::Load(Source::Const(ByteInWord::Lo, 0)),
-        Opcode::Sub(Source::Const(ByteInWord::Lo, 1)),
-        // assert carry
-        Opcode::Status,
-        Opcode::And(Source::Const(ByteInWord::Lo, 0x04)),
-        Opcode::If(Condition::Zero),
-        Opcode::Trap,
-        // 7FFF - 8000 => carry
-        Opcode::LoadImmediateWord(0x8000),
-        Opcode::Store(Source::Ram(RelativeTo::DataPointer, AddressingMode::Direct, 0xF0)),
-        Opcode::LoadImmediateWord(0x7FFF),
-        Opcode::Sub(Source::Ram(RelativeTo::DataPointer, AddressingMode::Direct, 0xF0)),
-        // assert carry
-        Opcode::Status,
-        Opcode::And(Source::Const(ByteInWord::Lo, 0x04)),
-        Opcode::If(Condition::Zero),
-        Opcode::Trap,
-        // 8000 - 8001 => carry
-        Opcode::LoadImmediateWord(0x8001),
-        Opcode::Store(Source::Ram(RelativeTo::DataPointer, AddressingMode::Direct, 0xF0)),
-        Opcode::LoadImmediateWord(0x8000),
-        Opcode::Sub(Source::Ram(RelativeTo::DataPointer, AddressingMode::Direct, 0xF0)),
-        // assert carry
-        Opcode::Status,
-        Opcode::And(Source::Const(ByteInWord::Lo, 0x04)),
-        Opcode::If(Condition::Zero),
-        Opcode::Trap,
-        // done
-        Opcode::Load(Source::Const(ByteInWord::Lo, 1)),
-        Opcode::OutLo,
-    ]).unwrap();
-
-    run("op_not_carry.mem", &[
-        Opcode::Load(Source::Const(ByteInWord::Lo, 0)),
-        Opcode::Not,
-        // assert carry
-        Opcode::Status,
-        Opcode::And(Source::Const(ByteInWord::Lo, 0x04)),
-        Opcode::If(Condition::Zero),
-        Opcode::Trap,
-        // value does not matter
-        Opcode::LoadImmediateWord(0xFFFF),
-        Opcode::Not,
-        // assert carry
-        Opcode::Status,
-        Opcode::And(Source::Const(ByteInWord::Lo, 0x04)),
-        Opcode::If(Condition::Zero),
-        Opcode::Trap,
-        // done
-        Opcode::Load(Source::Const(ByteInWord::Lo, 1)),
-        Opcode::OutLo,
-    ]).unwrap();
-
-    run("op_shift_carry.mem", &[
-        // 1 >> 1 => carry
-        Opcode::Load(Source::Const(ByteInWord::Lo, 1)),
-        Opcode::Shift(Direction::Right, ShiftSource::Const(1)),
-        // assert carry
-        Opcode::Status,
-        Opcode::And(Source::Const(ByteInWord::Lo, 0x04)),
-        Opcode::If(Condition::Zero),
-        Opcode::Trap,
-        // 0x8000 << 1 => carry
-        Opcode::LoadImmediateWord(0x8000),
-        Opcode::Shift(Direction::Left, ShiftSource::Const(1)),
-        // assert carry
-        Opcode::Status,
-        Opcode::And(Source::Const(ByteInWord::Lo, 0x04)),
-        Opcode::If(Condition::Zero),
-        Opcode::Trap,
-        // done
-        Opcode::Load(Source::Const(ByteInWord::Lo, 1)),
-        Opcode::OutLo,
-    ]).unwrap();
+/// The image of a program to analyze, linking several inputs, along with
+/// its labels and source lines where known.
+fn load_program(opts: &Options) -> Result<(Vec<u8>, DebugInfo), Box<dyn std::error::Error>> {
+    if opts.inputs.len() > 1 || opts.input.ends_with(".o") {
+        let linked = link_inputs(opts)?;
+        return Ok((linked.image, linked.debug));
+    }
+    let loaded = load(opts)?;
+    let debug = match &loaded.program {
+        Some((program, _)) => DebugInfo::from_program(program, &opts.input),
+        None => DebugInfo::default(),
+    };
+    Ok((loaded.bytes, debug))
+}
 
-    run("op_load_indirect.mem", &[
-        Opcode::Branch(Target::I11(4)), // skip over these
-        Opcode::Text(0),
-        Opcode::Text(0x04), // pointer to V
-        Opcode::Text(0),
-        Opcode::Text(0x42),
-        Opcode::Load(Source::Const(ByteInWord::Lo, 0x04)), // pointer to second ^
-        Opcode::Push,
-        // data direct
-        Opcode::Load(Source::Const(ByteInWord::Lo, 0x02)), // pointer to first ^
-        Opcode::LoadIndirect(RelativeTo::DataPointer, AddressingMode::Direct),
-        // assert eq 0x04
-        Opcode::Sub(Source::Const(ByteInWord::Lo, 0x04)),
-        Opcode::If(Condition::NotZero),
-        Opcode::Trap,
-        // data indirect
-        Opcode::Load(Source::Const(ByteInWord::Lo, 0x02)), // pointer to first ^
-        Opcode::LoadIndirect(RelativeTo::DataPointer, AddressingMode::Indirect),
-        // assert eq 0x42
-        Opcode::Sub(Source::Const(ByteInWord::Lo, 0x42)),
-        Opcode::If(Condition::NotZero),
-        Opcode::Trap,
-        // stack direct
-        Opcode::Load(Source::Const(ByteInWord::Lo, 0x00)), // top of stack
-        Opcode::LoadIndirect(RelativeTo::StackPointer, AddressingMode::Direct),
-        // assert eq 0x04
-        Opcode::Sub(Source::Const(ByteInWord::Lo, 0x04)),
-        Opcode::If(Condition::NotZero),
-        Opcode::Trap,
-        // stack indirect
-        Opcode::Load(Source::Const(ByteInWord::Lo, 0x00)), // top of stack
-        Opcode::LoadIndirect(RelativeTo::StackPointer, AddressingMode::Indirect),
-        // assert eq 0x42
-        Opcode::Sub(Source::Const(ByteInWord::Lo, 0x42)),
-        Opcode::If(Condition::NotZero),
-        Opcode::Trap,
-        // done
-        Opcode::Load(Source::Const(ByteInWord::Lo, 1)),
-        Opcode::OutLo,
-    ]).unwrap();
+fn cfg_cmd(opts: &Options) -> CmdResult {
+    let (image, debug) = load_program(opts)?;
+    let cfg = cfg::Cfg::recover(&image, RESET);
+    let mut out = open_output(opts.output.as_deref())?;
+    cfg.write_dot(&mut out, &debug.labels)?;
+    out.flush()?;
+    Ok(())
+}
 
-    fn run(filename: &str, insts: &[Opcode]) -> std::io::Result<()> {
-        let encoded = insts.iter().map(|i| i.encode()).collect::<Vec<_>>();
+fn stack_cmd(opts: &Options) -> CmdResult {
+    let (image, debug) = load_program(opts)?;
+    let cfg = cfg::Cfg::recover(&image, RESET);
+    let analysis = stack::analyze(&cfg, RESET);
+    let name = |addr: u16| match debug.labels.iter().find(|label| label.addr == addr) {
+        Some(label) => label.name.clone(),
+        None if addr == RESET => "(entry)".into(),
+        None => format!("{addr:#06X}"),
+    };
+
+    let mut out = open_output(opts.output.as_deref())?;
+    writeln!(out, "{:<16} {:<7} {:>5}  {:<9}  calls", "function", "entry", "frame", "worst")?;
+    for function in &analysis.functions {
+        let worst = match function.worst {
+            Some(worst) => worst.to_string(),
+            None if function.recursive => "recursive".into(),
+            None => "unbounded".into(),
+        };
+        let mut calls = function.calls.iter().map(|callee| name(*callee)).collect::<Vec<_>>();
+        if function.unresolved {
+            calls.push("CALL a".into());
+        }
+        let line = format!("{:<16} {:#06X} {:>6}  {worst:<9}  {}", name(function.entry), function.entry, function.frame, calls.join(", "));
+        writeln!(out, "{}", line.trim_end())?;
+    }
+    let top = opts.layout.stack_top;
+    match analysis.worst() {
+        Some(0) => writeln!(out, "stack: unused")?,
+        Some(worst) => match top.checked_sub(worst) {
+            Some(bottom) => writeln!(out, "stack: at most {worst} bytes, down to {bottom:#06X}")?,
+            None => writeln!(out, "stack: at most {worst} bytes")?,
+        },
+        None => writeln!(out, "stack: unbounded, because of recursion")?,
+    }
+    out.flush()?;
+
+    // Name the source line of each problem where it is known.
+    let mut problems = analysis
+        .problems
+        .iter()
+        .map(|problem| {
+            let addr = usize::from(problem.addr);
+            let line = debug.lines.iter().find(|line| {
+                let start = usize::from(line.addr);
+                (start..start + line.size).contains(&addr)
+            });
+            match line {
+                Some(line) => format!("{}:{}: {:#06X}: {}", line.file, line.line, problem.addr, problem.message),
+                None => format!("{:#06X}: {}", problem.addr, problem.message),
+            }
+        })
+        .collect::<Vec<_>>();
+    match analysis.worst() {
+        Some(worst) if top.checked_sub(worst).is_none() => {
+            problems.push(format!("the stack needs {worst} bytes and overflows past 0x0000"));
+        }
+        Some(worst) if worst > opts.layout.stack_size => problems.push(format!(
+            "the stack needs {worst} bytes, more than the {:#X} reserved for it (`--layout stack-size=...`)",
+            opts.layout.stack_size
+        )),
+        _ => {}
+    }
+    for problem in &problems {
+        eprintln!("{problem}");
+    }
+    match problems.len() {
+        0 => Ok(()),
+        n => Err(format!("{n} stack problem(s)").into()),
+    }
+}
 
-        let bytes = {
-            let mut bytes = vec![];
-            for e in encoded {
-                match e {
-                    Encoded::U8(b) => bytes.push(b),
-                    Encoded::U16(w) => {
-                        let bs = w.to_le_bytes();
-                        bytes.push(bs[1]);
-                        bytes.push(bs[0]);
-                    }
-                    Encoded::U8U16(b, w) => {
-                        bytes.push(b);
-                        let bs = w.to_le_bytes();
-                        bytes.push(bs[1]);
-                        bytes.push(bs[0]);
-                    }
-                }
+fn run_cmd(opts: &Options) -> CmdResult {
+    let bytes = load_image(opts)?;
+    let mut out = open_output(opts.output.as_deref())?;
+
+    let mut machine = sim::Machine::new(&bytes);
+    machine.input = opts.data;
+
+    for _ in 0..opts.max_steps {
+        let pc = machine.pc;
+        if opts.trace {
+            let fetch = [0, 1, 2].map(|i| machine.mem[usize::from(pc.wrapping_add(i))]);
+            let inst = Opcode::decode(&fetch).map_or_else(|| "??".into(), |op| op.to_string());
+            let skip = if machine.skip { " (skip)" } else { "" };
+            writeln!(
+                out,
+                "{pc:04X}: {inst:<20} A={:04X} SP={:04X} DP={:04X} S={:02X}{skip}",
+                machine.accum,
+                machine.sp,
+                machine.dp,
+                machine.status(),
+            )?;
+        }
+        match machine.step() {
+            sim::Step::Continue => {}
+            sim::Step::Output(b) => writeln!(out, "out {b:#04X} ({b})")?,
+            sim::Step::Trap => writeln!(out, "trap at {pc:#06X}")?,
+            sim::Step::Halt => {
+                writeln!(out, "halt at {pc:#06X}")?;
+                out.flush()?;
+                return Ok(());
             }
-            while bytes.len() < 65536 {
-                bytes.push(0);
+            sim::Step::Fault => {
+                writeln!(out, "fault at {pc:#06X}")?;
+                out.flush()?;
+                return Err("machine faulted".into());
             }
-            bytes
-        };
+        }
+    }
+    writeln!(out, "stopped after {} instructions at {:#06X}", opts.max_steps, machine.pc)?;
+    out.flush()?;
+    Ok(())
+}
 
-        use std::io::Write;
-        let mut f = std::fs::File::create(format!("../test/mem/{filename}"))?;
+fn dump_cmd(opts: &Options) -> CmdResult {
+    let bytes = load_image(opts)?;
+    let mut out = open_output(opts.output.as_deref())?;
+    let end = bytes.iter().rposition(|b| *b != 0).map_or(0, |i| i + 1);
+    for (i, row) in bytes[..end].chunks(opts.width).enumerate() {
+        let hex = row.iter().map(|b| format!("{b:02X}")).collect::<Vec<_>>().join(" ");
+        let text: String = row
+            .iter()
+            .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+            .collect();
+        writeln!(out, "{:04X}: {hex:<width$}  {text}", i * opts.width, width = opts.width * 3 - 1)?;
+    }
+    out.flush()?;
+    Ok(())
+}
 
-        for g in bytes.chunks(4) {
-            writeln!(f, "{:02X}{:02X}{:02X}{:02X}", g[3], g[2], g[1], g[0])?;
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Options {
+        parse_args(args.iter().map(|arg| arg.to_string())).unwrap()
+    }
 
-        Ok(())
+    #[test]
+    fn fill() {
+        assert_eq!(parse(&["assemble", "a.s", "--layout", "fill=0xFF"]).layout.fill, 0xFF);
+        assert_eq!(parse(&["assemble", "a.s", "--fill", "0x11", "--layout", "fill=0xFF"]).layout.fill, 0x11);
+        assert_eq!(parse(&["assemble", "a.s", "--layout", "fill=0xFF", "--fill", "0x11"]).layout.fill, 0x11);
+        assert_eq!(parse(&["assemble", "a.s", "--fill", "0x11", "--layout", "code=0x10"]).layout.fill, 0x11);
     }
 }
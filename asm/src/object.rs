@@ -0,0 +1,256 @@
+//! Relocatable object files: sections that are not yet placed in memory,
+//! the symbols they define, and the fields the linker fills in once they
+//! are.
+//!
+//! Objects are stored as text, one record per line:
+//!
+//! ```text
+//! cora16-object 1
+//! section .text 0x0022 align 1
+//! bytes 0x0000 86020BF000C0179801F000C01104D00003048604980204D000038E0003068001
+//! bytes 0x0020 0600
+//! section .data 0x0000 align 1
+//! section .bss 0x0000 align 1
+//! symbol global fib_fn .text 0x0000
+//! symbol local just_one .text 0x001E
+//! reloc .text 0x000E call .text 0
+//! source asm/lib/fib.s
+//! line .text 0x0000 2 3
+//! ```
+//!
+//! A relocation names either one of the object's own sections or a global
+//! symbol from another object.
+
+use std::io::{self, Write};
+
+use crate::assemble::Section;
+
+const MAGIC: &str = "cora16-object 1";
+
+#[derive(Debug, Clone, Default)]
+pub struct Object {
+    /// The contents of `.text`, `.data` and `.bss`, which is always zero.
+    pub sections: [Vec<u8>; 3],
+    /// The alignment each section must be placed at.
+    pub aligns: [usize; 3],
+    pub symbols: Vec<Symbol>,
+    pub relocs: Vec<Reloc>,
+    /// The file the object was assembled from, if known.
+    pub source: Option<String>,
+    pub lines: Vec<SourceLine>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub section: Section,
+    pub offset: u16,
+    /// Whether other objects may refer to the symbol (`.global`).
+    pub global: bool,
+}
+
+/// The fields a relocation may fill in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelocKind {
+    /// The eleven bit address of a `Call`.
+    Call,
+    /// The eleven bit offset of a `Branch`, relative to the instruction
+    /// after it.
+    Branch,
+    /// A big-endian word: the operand of `CallWord` or `LoadImmediateWord`,
+    /// or a `.word`.
+    Word,
+    /// The byte of a `Source::Const` selecting the high byte of a word.
+    Hi,
+    /// The byte of a `Source::Const` selecting the low byte of a word.
+    Lo,
+}
+
+impl RelocKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            RelocKind::Call => "call",
+            RelocKind::Branch => "branch",
+            RelocKind::Word => "word",
+            RelocKind::Hi => "hi",
+            RelocKind::Lo => "lo",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<RelocKind> {
+        [RelocKind::Call, RelocKind::Branch, RelocKind::Word, RelocKind::Hi, RelocKind::Lo]
+            .into_iter()
+            .find(|kind| kind.name() == name)
+    }
+}
+
+/// A field at `offset` in `section` that holds the address of `symbol`
+/// plus `addend`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reloc {
+    pub section: Section,
+    pub offset: u16,
+    pub kind: RelocKind,
+    /// A section name such as `.text` for this object's own sections, or
+    /// a global symbol.
+    pub symbol: String,
+    pub addend: i64,
+}
+
+/// The `size` bytes at `offset` in `section` came from `line` of the
+/// source; `expanded` if they are part of a macro or pseudo-instruction's
+/// expansion.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLine {
+    pub section: Section,
+    pub offset: u16,
+    pub size: usize,
+    pub line: usize,
+    pub expanded: bool,
+}
+
+fn section(name: &str) -> Option<Section> {
+    [Section::Text, Section::Data, Section::Bss].into_iter().find(|s| s.name() == name)
+}
+
+impl Object {
+    pub fn write(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "{MAGIC}")?;
+        for section in [Section::Text, Section::Data, Section::Bss] {
+            let bytes = &self.sections[section as usize];
+            let align = self.aligns[section as usize];
+            writeln!(out, "section {} {:#06X} align {align}", section.name(), bytes.len())?;
+            if section == Section::Bss {
+                continue;
+            }
+            for (i, chunk) in bytes.chunks(32).enumerate() {
+                let hex = chunk.iter().map(|b| format!("{b:02X}")).collect::<String>();
+                writeln!(out, "bytes {:#06X} {hex}", i * 32)?;
+            }
+        }
+        for symbol in &self.symbols {
+            let binding = if symbol.global { "global" } else { "local" };
+            writeln!(out, "symbol {binding} {} {} {:#06X}", symbol.name, symbol.section.name(), symbol.offset)?;
+        }
+        for reloc in &self.relocs {
+            writeln!(
+                out,
+                "reloc {} {:#06X} {} {} {}",
+                reloc.section.name(),
+                reloc.offset,
+                reloc.kind.name(),
+                reloc.symbol,
+                reloc.addend
+            )?;
+        }
+        if let Some(source) = &self.source {
+            writeln!(out, "source {source}")?;
+        }
+        for line in &self.lines {
+            let expanded = if line.expanded { " expanded" } else { "" };
+            writeln!(out, "line {} {:#06X} {} {}{expanded}", line.section.name(), line.offset, line.size, line.line)?;
+        }
+        Ok(())
+    }
+
+    pub fn read(text: &str) -> Result<Object, String> {
+        let mut lines = text.lines().enumerate().map(|(i, line)| (i + 1, line.trim()));
+        match lines.next() {
+            Some((_, MAGIC)) => {}
+            _ => return Err("not a CORA-16 object file".into()),
+        }
+
+        let mut object = Object::default();
+        let mut current = None;
+        for (line, text) in lines.filter(|(_, text)| !text.is_empty()) {
+            let err = |message: String| format!("line {line}: {message}");
+            let fields = text.split_whitespace().collect::<Vec<_>>();
+            let section = |name: &str| self::section(name).ok_or_else(|| err(format!("unknown section `{name}`")));
+            let number = |s: &str| parse_number(s).ok_or_else(|| err(format!("invalid number `{s}`")));
+            let offset = |s: &str| number(s)?.try_into().map_err(|_| err(format!("invalid offset `{s}`")));
+            if let Some(source) = text.strip_prefix("source ") {
+                object.source = Some(source.trim().to_string());
+                continue;
+            }
+            match fields.as_slice() {
+                ["section", name, size, "align", align] => {
+                    let s = section(name)?;
+                    // Checked before it is allocated: no section is bigger
+                    // than memory.
+                    let size = usize::try_from(number(size)?)
+                        .ok()
+                        .filter(|size| *size <= 0x10000)
+                        .ok_or_else(|| err(format!("invalid size `{size}`")))?;
+                    object.sections[s as usize] = vec![0; size];
+                    object.aligns[s as usize] = usize::try_from(number(align)?).map_err(|_| err("invalid alignment".into()))?;
+                    current = Some(s);
+                }
+                ["bytes", start, hex] => {
+                    let s = current.ok_or_else(|| err("`bytes` before any `section`".into()))?;
+                    let start: u16 = offset(start)?;
+                    let bytes = (0..hex.len())
+                        .step_by(2)
+                        .map(|i| hex.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+                        .collect::<Option<Vec<_>>>()
+                        .ok_or_else(|| err("invalid hex bytes".into()))?;
+                    let data = &mut object.sections[s as usize];
+                    let start = usize::from(start);
+                    data.get_mut(start..start + bytes.len())
+                        .ok_or_else(|| err(format!("bytes run past the end of {}", s.name())))?
+                        .copy_from_slice(&bytes);
+                }
+                ["symbol", binding @ ("global" | "local"), name, s, at] => object.symbols.push(Symbol {
+                    name: name.to_string(),
+                    section: section(s)?,
+                    offset: offset(at)?,
+                    global: *binding == "global",
+                }),
+                ["reloc", s, at, kind, symbol, addend] => object.relocs.push(Reloc {
+                    section: section(s)?,
+                    offset: offset(at)?,
+                    kind: RelocKind::from_name(kind).ok_or_else(|| err(format!("unknown relocation `{kind}`")))?,
+                    symbol: symbol.to_string(),
+                    addend: number(addend)?,
+                }),
+                ["line", s, at, size, line, rest @ ..] if matches!(rest, [] | ["expanded"]) => {
+                    object.lines.push(SourceLine {
+                        section: section(s)?,
+                        offset: offset(at)?,
+                        size: usize::try_from(number(size)?).map_err(|_| err("invalid size".into()))?,
+                        line: usize::try_from(number(line)?).map_err(|_| err("invalid line".into()))?,
+                        expanded: !rest.is_empty(),
+                    })
+                }
+                _ => return Err(err(format!("unexpected `{text}`"))),
+            }
+        }
+        Ok(object)
+    }
+}
+
+fn parse_number(s: &str) -> Option<i64> {
+    let (neg, s) = match s.strip_prefix('-') {
+        Some(s) => (true, s),
+        None => (false, s),
+    };
+    let n = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => i64::from_str_radix(hex, 16).ok()?,
+        None => s.parse().ok()?,
+    };
+    Some(if neg { -n } else { n })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn section_sizes() {
+        let object = Object::read(&format!("{MAGIC}\nsection .text 0x10000 align 1\n")).unwrap();
+        assert_eq!(object.sections[Section::Text as usize].len(), 0x10000);
+        let err = Object::read(&format!("{MAGIC}\nsection .text 0x10001 align 1\n")).unwrap_err();
+        assert_eq!(err, "line 2: invalid size `0x10001`");
+        let err = Object::read(&format!("{MAGIC}\nsection .data 0xFFFFFFFFFFFF align 1\n")).unwrap_err();
+        assert_eq!(err, "line 2: invalid size `0xFFFFFFFFFFFF`");
+    }
+}
@@ -0,0 +1,190 @@
+use std::io::{self, Write};
+
+pub const MEMORY_SIZE: usize = 0x10000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// 32-bit words in the byte order `test/sim_sram.v` expects.
+    Readmemh,
+    /// One byte per line, for `$readmemh` into a byte-wide memory.
+    ReadmemhBytes,
+    Binary,
+    IntelHex,
+    /// A C array for building into the RP2040 SPI RAM emulator firmware.
+    SpiRamEmu,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Format> {
+        match name {
+            "readmemh" => Some(Format::Readmemh),
+            "readmemh-bytes" => Some(Format::ReadmemhBytes),
+            "bin" | "binary" => Some(Format::Binary),
+            "ihex" | "intel-hex" => Some(Format::IntelHex),
+            "spi-ram-emu" => Some(Format::SpiRamEmu),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Readmemh | Format::ReadmemhBytes => "mem",
+            Format::Binary => "bin",
+            Format::IntelHex => "hex",
+            Format::SpiRamEmu => "h",
+        }
+    }
+
+    /// The testbench reads the whole SPI RAM with `$readmemh`, so word
+    /// images default to a full 64 KiB; everything else is sized to the
+    /// program.
+    pub fn default_padding(&self) -> Padding {
+        match self {
+            Format::Readmemh => Padding::Full,
+            _ => Padding::None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Padding {
+    None,
+    Full,
+    To(usize),
+}
+
+impl Padding {
+    pub fn from_name(name: &str) -> Option<Padding> {
+        match name {
+            "none" => Some(Padding::None),
+            "full" => Some(Padding::Full),
+            _ => {
+                let n = match name.strip_prefix("0x") {
+                    Some(hex) => usize::from_str_radix(hex, 16).ok()?,
+                    None => name.parse().ok()?,
+                };
+                (n <= MEMORY_SIZE).then_some(Padding::To(n))
+            }
+        }
+    }
+}
+
+pub fn write(out: &mut dyn Write, format: Format, padding: Padding, bytes: &[u8]) -> io::Result<()> {
+    let mut bytes = bytes.to_vec();
+    match padding {
+        Padding::None => {}
+        Padding::Full => bytes.resize(MEMORY_SIZE.max(bytes.len()), 0),
+        Padding::To(n) => bytes.resize(n.max(bytes.len()), 0),
+    }
+
+    match format {
+        Format::Readmemh => write_readmemh(out, &bytes),
+        Format::ReadmemhBytes => write_readmemh_bytes(out, &bytes),
+        Format::Binary => out.write_all(&bytes),
+        Format::IntelHex => write_intel_hex(out, &bytes),
+        Format::SpiRamEmu => write_spi_ram_emu(out, &bytes),
+    }
+}
+
+fn write_readmemh(out: &mut dyn Write, bytes: &[u8]) -> io::Result<()> {
+    for g in bytes.chunks(4) {
+        let mut g = g.to_vec();
+        g.resize(4, 0);
+        writeln!(out, "{:02X}{:02X}{:02X}{:02X}", g[3], g[2], g[1], g[0])?;
+    }
+    Ok(())
+}
+
+fn write_readmemh_bytes(out: &mut dyn Write, bytes: &[u8]) -> io::Result<()> {
+    for b in bytes {
+        writeln!(out, "{b:02X}")?;
+    }
+    Ok(())
+}
+
+fn write_intel_hex(out: &mut dyn Write, bytes: &[u8]) -> io::Result<()> {
+    for (i, row) in bytes.chunks(16).enumerate() {
+        let addr = (i * 16) as u16;
+        let [hi, lo] = addr.to_be_bytes();
+        let mut record = vec![row.len() as u8, hi, lo, 0x00];
+        record.extend(row);
+        write_record(out, &record)?;
+    }
+    write_record(out, &[0x00, 0x00, 0x00, 0x01])
+}
+
+fn write_record(out: &mut dyn Write, record: &[u8]) -> io::Result<()> {
+    let sum = record.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+    write!(out, ":")?;
+    for b in record {
+        write!(out, "{b:02X}")?;
+    }
+    writeln!(out, "{:02X}", sum.wrapping_neg())
+}
+
+fn write_spi_ram_emu(out: &mut dyn Write, bytes: &[u8]) -> io::Result<()> {
+    writeln!(out, "// SPI RAM image, loaded from address 0")?;
+    writeln!(out, "#define SPI_RAM_IMAGE_SIZE {}", bytes.len())?;
+    writeln!(out, "static const unsigned char spi_ram_image[SPI_RAM_IMAGE_SIZE] = {{")?;
+    for row in bytes.chunks(16) {
+        let line = row.iter().map(|b| format!("0x{b:02x},")).collect::<Vec<_>>().join(" ");
+        writeln!(out, "    {line}")?;
+    }
+    writeln!(out, "}};")
+}
+
+/// Reads any of the hex formats back into a flat image.  Binary images
+/// need no parsing.
+pub fn read(format: Format, data: &[u8]) -> Result<Vec<u8>, String> {
+    let text = || std::str::from_utf8(data).map_err(|_| "input is not text".to_string());
+    match format {
+        Format::Binary => Ok(data.to_vec()),
+        Format::Readmemh => read_readmemh(text()?, 4),
+        Format::ReadmemhBytes => read_readmemh(text()?, 1),
+        Format::IntelHex => read_intel_hex(text()?),
+        Format::SpiRamEmu => Err("cannot read spi-ram-emu images".into()),
+    }
+}
+
+fn read_readmemh(text: &str, width: usize) -> Result<Vec<u8>, String> {
+    let mut bytes = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split("//").next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let word = u32::from_str_radix(line, 16).map_err(|_| format!("line {}: invalid word `{line}`", i + 1))?;
+        bytes.extend(&word.to_le_bytes()[..width]);
+    }
+    Ok(bytes)
+}
+
+fn read_intel_hex(text: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let err = || format!("line {}: invalid record", i + 1);
+        let Some(hex) = line.trim().strip_prefix(':') else {
+            continue;
+        };
+        let record = (0..hex.len() / 2)
+            .map(|j| u8::from_str_radix(hex.get(j * 2..j * 2 + 2).ok_or_else(err)?, 16).map_err(|_| err()))
+            .collect::<Result<Vec<_>, _>>()?;
+        if record.len() < 5 || record.iter().fold(0u8, |s, b| s.wrapping_add(*b)) != 0 {
+            return Err(err());
+        }
+        let len = usize::from(record[0]);
+        let addr = usize::from(u16::from_be_bytes([record[1], record[2]]));
+        match record[3] {
+            0x00 => {
+                let data = record.get(4..4 + len).ok_or_else(err)?;
+                if bytes.len() < addr + len {
+                    bytes.resize(addr + len, 0);
+                }
+                bytes[addr..addr + len].copy_from_slice(data);
+            }
+            0x01 => break,
+            _ => return Err(format!("line {}: unsupported record type", i + 1)),
+        }
+    }
+    Ok(bytes)
+}
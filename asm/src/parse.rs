@@ -0,0 +1,477 @@
+use std::ops::Range;
+
+use crate::diag::{Diagnostic, Diagnostics};
+use crate::{AddressingMode, ByteInWord, RelativeTo};
+
+pub struct Statement {
+    pub line: usize,
+    /// Whether the statement came from a macro expansion on `line`.
+    pub expanded: bool,
+    pub label: Option<String>,
+    pub inst: Option<Instruction>,
+}
+
+pub struct Instruction {
+    pub mnemonic: String,
+    pub operands: Vec<Operand>,
+    /// Where the mnemonic and each operand sit in the line's text, which
+    /// for an expanded statement is the macro body rather than the source.
+    pub span: Range<usize>,
+    pub spans: Vec<Range<usize>>,
+}
+
+pub enum Operand {
+    Imm(Value),
+    Value(Value),
+    Input(ByteInWord),
+    Accum,
+    Ram(RelativeTo, AddressingMode, Value),
+    RamAccum(RelativeTo, AddressingMode),
+    Str(Vec<u8>),
+}
+
+pub enum Value {
+    Number(i64),
+    Label(String),
+    Neg(Box<Value>),
+    Binary(BinOp, Box<Value>, Box<Value>),
+    /// `hi(x)`: the high byte of `x`.
+    Hi(Box<Value>),
+    /// `lo(x)`: the low byte of `x`.
+    Lo(Box<Value>),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    And,
+    Or,
+    Shl,
+    Shr,
+}
+
+impl Value {
+    /// Every label or constant the value refers to.
+    pub fn symbols(&self) -> Vec<&str> {
+        match self {
+            Value::Number(_) => vec![],
+            Value::Label(name) => vec![name.as_str()],
+            Value::Neg(v) | Value::Hi(v) | Value::Lo(v) => v.symbols(),
+            Value::Binary(_, l, r) => {
+                let mut names = l.symbols();
+                names.extend(r.symbols());
+                names
+            }
+        }
+    }
+
+    /// Whether `hi()` or `lo()` appears anywhere in the value.
+    pub fn has_byte(&self) -> bool {
+        match self {
+            Value::Number(_) | Value::Label(_) => false,
+            Value::Hi(_) | Value::Lo(_) => true,
+            Value::Neg(v) => v.has_byte(),
+            Value::Binary(_, l, r) => l.has_byte() || r.has_byte(),
+        }
+    }
+}
+
+pub fn parse(src: &str) -> Result<Vec<Statement>, Diagnostics> {
+    let (stmts, errors) = parse_recovering(src);
+    if errors.is_empty() {
+        Ok(stmts)
+    } else {
+        Err(Diagnostics(errors))
+    }
+}
+
+/// Parses every line, reporting each one that fails and leaving it out
+/// rather than stopping at the first.
+pub fn parse_recovering(src: &str) -> (Vec<Statement>, Vec<Diagnostic>) {
+    let mut stmts = vec![];
+    let (lines, mut errors) = crate::macros::expand(src);
+    for crate::macros::Line { line, text, expanded } in lines {
+        let err = |message: String, span: Range<usize>| {
+            let diag = Diagnostic::error(line, message);
+            if expanded {
+                diag.note("in code expanded from this line")
+            } else {
+                diag.at(Some(span))
+            }
+        };
+        let (tokens, spans) = match tokenize(&text, expanded) {
+            Ok(tokens) => tokens,
+            Err((message, span)) => {
+                errors.push(err(message, span));
+                continue;
+            }
+        };
+        if tokens.is_empty() {
+            continue;
+        }
+        let mut cursor = Cursor { tokens: &tokens, spans: &spans, pos: 0 };
+        match parse_tokens(&mut cursor) {
+            Ok((label, inst)) => stmts.push(Statement { line, expanded, label, inst }),
+            Err(message) => errors.push(err(message, cursor.span())),
+        }
+    }
+    errors.sort_by_key(|diag| diag.line);
+    (stmts, errors)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(i64),
+    Str(Vec<u8>),
+    Punct(char),
+    Shl,
+    Shr,
+}
+
+/// A line's tokens, and the bytes of the line each came from.
+type Tokens = (Vec<Token>, Vec<Range<usize>>);
+
+/// Splits a line into tokens.  An error carries the bytes at fault.  Only
+/// a macro expansion may use `$` in names, for the labels `\@` makes.
+fn tokenize(text: &str, expanded: bool) -> Result<Tokens, (String, Range<usize>)> {
+    let ident = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '.' || (expanded && c == '$');
+    let mut tokens = vec![];
+    let mut spans = vec![];
+    let mut chars = text.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        let end = |chars: &mut std::iter::Peekable<std::str::CharIndices>| chars.peek().map_or(text.len(), |(i, _)| *i);
+        if c == ';' {
+            break;
+        } else if c.is_whitespace() {
+            chars.next();
+            continue;
+        } else if c.is_ascii_digit() {
+            let mut s = String::new();
+            while let Some(&(_, c)) = chars.peek() {
+                if !c.is_ascii_alphanumeric() && c != '_' {
+                    break;
+                }
+                s.push(c);
+                chars.next();
+            }
+            let n = parse_number(&s).map_err(|message| (message, start..end(&mut chars)))?;
+            tokens.push(Token::Number(n));
+        } else if ident(c) {
+            let mut s = String::new();
+            while let Some(&(_, c)) = chars.peek() {
+                if !ident(c) {
+                    break;
+                }
+                s.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Ident(s));
+        } else if c == '"' {
+            chars.next();
+            let s = string(&mut chars).map_err(|message| (message, start..end(&mut chars)))?;
+            tokens.push(Token::Str(s));
+        } else if c == '<' || c == '>' {
+            chars.next();
+            if chars.next().map(|(_, c)| c) != Some(c) {
+                return Err((format!("unexpected character `{c}`"), start..start + 1));
+            }
+            tokens.push(if c == '<' { Token::Shl } else { Token::Shr });
+        } else if "#[]+-*/&|(),:".contains(c) {
+            tokens.push(Token::Punct(c));
+            chars.next();
+        } else {
+            return Err((format!("unexpected character `{c}`"), start..start + c.len_utf8()));
+        }
+        spans.push(start..end(&mut chars));
+    }
+    Ok((tokens, spans))
+}
+
+fn string(chars: &mut std::iter::Peekable<std::str::CharIndices>) -> Result<Vec<u8>, String> {
+    let mut chars = chars.by_ref().map(|(_, c)| c);
+    let mut bytes = vec![];
+    loop {
+        let c = match chars.next() {
+            None => return Err("unterminated string".into()),
+            Some('"') => return Ok(bytes),
+            Some('\\') => match chars.next() {
+                Some('n') => '\n',
+                Some('r') => '\r',
+                Some('t') => '\t',
+                Some('0') => '\0',
+                Some(c @ ('\\' | '"' | '\'')) => c,
+                Some('x') => {
+                    let hex = chars.by_ref().take(2).collect::<String>();
+                    let b = u8::from_str_radix(&hex, 16).map_err(|_| format!("invalid escape `\\x{hex}`"))?;
+                    bytes.push(b);
+                    continue;
+                }
+                Some(c) => return Err(format!("invalid escape `\\{c}`")),
+                None => return Err("unterminated string".into()),
+            },
+            Some(c) => c,
+        };
+        if !c.is_ascii() {
+            return Err(format!("non-ASCII character `{c}` in string"));
+        }
+        bytes.push(c as u8);
+    }
+}
+
+fn parse_number(s: &str) -> Result<i64, String> {
+    let digits = s.replace('_', "");
+    let lower = digits.to_ascii_lowercase();
+    let res = if let Some(hex) = lower.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16)
+    } else if let Some(bin) = lower.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2)
+    } else {
+        lower.parse()
+    };
+    res.map_err(|_| format!("invalid number `{s}`"))
+}
+
+struct Cursor<'a> {
+    tokens: &'a [Token],
+    spans: &'a [Range<usize>],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    /// The bytes of the next token, or the empty span just past the last
+    /// one at the end of the line.
+    fn span(&self) -> Range<usize> {
+        match self.spans.get(self.pos) {
+            Some(span) => span.clone(),
+            None => self.spans.last().map_or(0..0, |span| span.end..span.end),
+        }
+    }
+
+    /// The bytes from the token at `start` to the last one consumed.
+    fn span_from(&self, start: usize) -> Range<usize> {
+        let end = self.spans.get(self.pos.saturating_sub(1)).map_or(0, |span| span.end);
+        self.spans.get(start).map_or(end..end, |span| span.start..end)
+    }
+
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn peek_at(&self, n: usize) -> Option<&'a Token> {
+        self.tokens.get(self.pos + n)
+    }
+
+    fn next(&mut self) -> Option<&'a Token> {
+        let t = self.tokens.get(self.pos);
+        self.pos += 1;
+        t
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(&Token::Punct(c)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(format!("expected `{c}`"))
+        }
+    }
+
+    /// Parses an expression.  Precedence follows C: `* /` bind tightest,
+    /// then `+ -`, `<< >>`, `&` and finally `|`.
+    fn value(&mut self) -> Result<Value, String> {
+        self.binary(0)
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Value, String> {
+        const LEVELS: [&[(Token, BinOp)]; 5] = [
+            &[(Token::Punct('|'), BinOp::Or)],
+            &[(Token::Punct('&'), BinOp::And)],
+            &[(Token::Shl, BinOp::Shl), (Token::Shr, BinOp::Shr)],
+            &[(Token::Punct('+'), BinOp::Add), (Token::Punct('-'), BinOp::Sub)],
+            &[(Token::Punct('*'), BinOp::Mul), (Token::Punct('/'), BinOp::Div)],
+        ];
+        let Some(ops) = LEVELS.get(level) else {
+            return self.unary();
+        };
+        let mut lhs = self.binary(level + 1)?;
+        while let Some((_, op)) = ops.iter().find(|(t, _)| self.peek() == Some(t)) {
+            self.pos += 1;
+            let rhs = self.binary(level + 1)?;
+            lhs = Value::Binary(*op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Value, String> {
+        if self.eat('-') {
+            return Ok(Value::Neg(Box::new(self.unary()?)));
+        }
+        if self.eat('+') {
+            return self.unary();
+        }
+        if self.eat('(') {
+            let v = self.value()?;
+            self.expect(')')?;
+            return Ok(v);
+        }
+        match self.next() {
+            Some(Token::Number(n)) => Ok(Value::Number(*n)),
+            Some(Token::Ident(name)) if self.peek() == Some(&Token::Punct('(')) => {
+                let wrap = match name.to_ascii_lowercase().as_str() {
+                    "hi" => Value::Hi,
+                    "lo" => Value::Lo,
+                    _ => return Err(format!("unknown function `{name}`")),
+                };
+                self.pos += 1;
+                let v = self.value()?;
+                self.expect(')')?;
+                Ok(wrap(Box::new(v)))
+            }
+            Some(Token::Ident(name)) => Ok(Value::Label(name.clone())),
+            _ => Err("expected a number or label".into()),
+        }
+    }
+}
+
+fn parse_tokens(cursor: &mut Cursor) -> Result<(Option<String>, Option<Instruction>), String> {
+    let mut label = None;
+    if let (Some(Token::Ident(name)), Some(Token::Punct(':'))) = (cursor.peek(), cursor.peek_at(1)) {
+        label = Some(name.clone());
+        cursor.pos += 2;
+    }
+
+    let span = cursor.span();
+    let mnemonic = match cursor.peek() {
+        Some(Token::Ident(name)) => name.to_ascii_uppercase(),
+        None => return Ok((label, None)),
+        _ => return Err("expected a mnemonic".into()),
+    };
+    cursor.next();
+
+    let mut operands = vec![];
+    let mut spans = vec![];
+    if cursor.peek().is_some() {
+        loop {
+            let start = cursor.pos;
+            operands.push(parse_operand(cursor)?);
+            spans.push(cursor.span_from(start));
+            if !cursor.eat(',') {
+                break;
+            }
+        }
+    }
+
+    if cursor.peek().is_some() {
+        return Err("unexpected tokens after operand".into());
+    }
+
+    Ok((label, Some(Instruction { mnemonic, operands, span, spans })))
+}
+
+fn parse_operand(cursor: &mut Cursor) -> Result<Operand, String> {
+    if let Some(Token::Str(s)) = cursor.peek() {
+        cursor.next();
+        return Ok(Operand::Str(s.clone()));
+    }
+
+    if cursor.eat('#') {
+        return Ok(Operand::Imm(cursor.value()?));
+    }
+
+    if cursor.eat('[') {
+        let mode = if cursor.eat('[') {
+            AddressingMode::Indirect
+        } else {
+            AddressingMode::Direct
+        };
+
+        let relative = match cursor.next() {
+            Some(Token::Ident(name)) => match name.to_ascii_lowercase().as_str() {
+                "dp" => RelativeTo::DataPointer,
+                "sp" => RelativeTo::StackPointer,
+                _ => return Err(format!("expected `dp` or `sp`, found `{name}`")),
+            },
+            _ => return Err("expected `dp` or `sp`".into()),
+        };
+
+        let mut offset = None;
+        if cursor.eat('+') {
+            match cursor.peek() {
+                Some(Token::Ident(name)) if name.eq_ignore_ascii_case("a") => {
+                    cursor.next();
+                }
+                _ => offset = Some(cursor.value()?),
+            }
+        } else {
+            offset = Some(Value::Number(0));
+        }
+
+        cursor.expect(']')?;
+        if let AddressingMode::Indirect = mode {
+            cursor.expect(']')?;
+        }
+
+        return Ok(match offset {
+            Some(a) => Operand::Ram(relative, mode, a),
+            None => Operand::RamAccum(relative, mode),
+        });
+    }
+
+    let alone = matches!(cursor.peek_at(1), None | Some(Token::Punct(',')));
+    if let (Some(Token::Ident(name)), true) = (cursor.peek(), alone) {
+        let op = match name.to_ascii_lowercase().as_str() {
+            "a" => Some(Operand::Accum),
+            "in" | "in.lo" => Some(Operand::Input(ByteInWord::Lo)),
+            "in.hi" => Some(Operand::Input(ByteInWord::Hi)),
+            _ => None,
+        };
+        if let Some(op) = op {
+            cursor.next();
+            return Ok(op);
+        }
+    }
+
+    Ok(Operand::Value(cursor.value()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rendered(src: &str) -> String {
+        let mut out = vec![];
+        parse(src).err().expect("an error").render(&mut out, "a.s", src).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn statements() {
+        let stmts = parse("start: LOAD [[sp+2]] ; comment\n\n    .word 1, end\n").unwrap();
+        assert_eq!(stmts.iter().map(|stmt| stmt.line).collect::<Vec<_>>(), [1, 3]);
+        assert_eq!(stmts[0].label.as_deref(), Some("start"));
+        let inst = stmts[0].inst.as_ref().unwrap();
+        assert_eq!((inst.mnemonic.as_str(), inst.spans.first()), ("LOAD", Some(&(12..20))));
+        assert_eq!(stmts[1].inst.as_ref().unwrap().operands.len(), 2);
+    }
+
+    #[test]
+    fn end_of_line() {
+        // The caret goes just past the end of the line, not under all of it.
+        let bracket = "error: expected `]`\n --> a.s:1:11\n  |\n1 | LOAD [sp+1\n  |           ^\n\n";
+        assert_eq!(rendered("LOAD [sp+1\n"), bracket);
+        let operand = "error: expected a number or label\n --> a.s:1:10\n  |\n1 | LOAD #1 ,\n  |          ^\n\n";
+        assert_eq!(rendered("LOAD #1 ,\n"), operand);
+    }
+}
@@ -0,0 +1,357 @@
+use crate::{AddressingMode, ByteInWord, Condition, Direction, Opcode, RelativeTo, ShiftSource, Source};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    Continue,
+    Output(u8),
+    Halt,
+    Trap,
+    Fault,
+}
+
+/// An instruction-level model of `src/cpu.v`.  Each call to `step` runs one
+/// instruction to completion, matching the architectural state the RTL
+/// reaches when it returns to `ST_INIT`.
+pub struct Machine {
+    pub mem: Vec<u8>,
+    pub accum: u16,
+    pub pc: u16,
+    pub sp: u16,
+    pub dp: u16,
+    pub zero: bool,
+    pub neg: bool,
+    pub carry: bool,
+    pub skip: bool,
+    pub skipped: bool,
+    pub input: u8,
+    pub output: u8,
+    pub halted: bool,
+    pub faulted: bool,
+}
+
+impl Machine {
+    pub fn new(image: &[u8]) -> Machine {
+        let mut mem = vec![0; 0x10000];
+        let len = image.len().min(mem.len());
+        mem[..len].copy_from_slice(&image[..len]);
+        Machine {
+            mem,
+            accum: 0,
+            pc: 0,
+            sp: 0,
+            dp: 0,
+            zero: false,
+            neg: false,
+            carry: false,
+            skip: false,
+            skipped: false,
+            input: 0,
+            output: 0,
+            halted: false,
+            faulted: false,
+        }
+    }
+
+    /// The value the `Status` instruction loads into the accumulator.
+    pub fn status(&self) -> u16 {
+        (u16::from(self.skipped) << 5)
+            | (u16::from(self.carry) << 2)
+            | (u16::from(self.neg) << 1)
+            | u16::from(self.zero)
+    }
+
+    pub fn read(&self, addr: u16) -> u16 {
+        let hi = self.mem[usize::from(addr)];
+        let lo = self.mem[usize::from(addr.wrapping_add(1))];
+        u16::from_be_bytes([hi, lo])
+    }
+
+    pub fn write(&mut self, addr: u16, value: u16) {
+        let [hi, lo] = value.to_be_bytes();
+        self.mem[usize::from(addr)] = hi;
+        self.mem[usize::from(addr.wrapping_add(1))] = lo;
+    }
+
+    pub fn step(&mut self) -> Step {
+        if self.faulted {
+            return Step::Fault;
+        }
+        if self.halted {
+            return Step::Halt;
+        }
+
+        let fetch = [0, 1, 2].map(|i| self.mem[usize::from(self.pc.wrapping_add(i))]);
+        let Some(op) = Opcode::decode(&fetch) else {
+            return self.fault();
+        };
+        let Ok(encoded) = op.encode() else {
+            return self.fault();
+        };
+        let next = self.pc.wrapping_add(encoded.len() as u16);
+
+        // Conditions and `Status` see the flags from before this instruction,
+        // just as the RTL's non-blocking assignments do.
+        let skip = self.skip;
+        let status = self.status();
+        self.skip = match &op {
+            Opcode::If(c) => self.condition(c),
+            _ => false,
+        };
+        self.skipped = skip;
+
+        // The RTL runs these regardless of a pending skip.
+        match op {
+            Opcode::Nop | Opcode::If(_) => {
+                self.pc = next;
+                return Step::Continue;
+            }
+            Opcode::Status => {
+                self.accum = status;
+                self.pc = next;
+                return Step::Continue;
+            }
+            _ => {}
+        }
+
+        if skip {
+            self.pc = next;
+            return Step::Continue;
+        }
+
+        let mut event = Step::Continue;
+        self.pc = next;
+        match op {
+            Opcode::Nop | Opcode::If(_) | Opcode::Status => unreachable!(),
+            Opcode::Text(_) => return self.fault(),
+            Opcode::Halt => {
+                self.halted = true;
+                event = Step::Halt;
+            }
+            Opcode::Trap => event = Step::Trap,
+            Opcode::OutLo => {
+                self.output = self.accum.to_le_bytes()[0];
+                event = Step::Output(self.output);
+            }
+            Opcode::OutHi => {
+                self.output = self.accum.to_le_bytes()[1];
+                event = Step::Output(self.output);
+            }
+            Opcode::Push => {
+                self.sp = self.sp.wrapping_sub(2);
+                self.write(self.sp, self.accum);
+            }
+            Opcode::Pop => {
+                self.accum = self.read(self.sp);
+                self.sp = self.sp.wrapping_add(2);
+            }
+            Opcode::Drop => self.sp = self.sp.wrapping_add(2),
+            Opcode::Return => {
+                self.pc = self.read(self.sp);
+                self.sp = self.sp.wrapping_add(2);
+            }
+            Opcode::BranchIndirect => self.pc = next.wrapping_add(self.accum),
+            Opcode::CallIndirect => self.call(next, self.accum),
+            Opcode::CallWord(w) => self.call(next, w),
+            Opcode::LoadImmediateWord(w) => self.accum = w,
+            Opcode::SetDataPointer => self.dp = self.accum,
+            Opcode::Not | Opcode::Test => self.alu(&op, 0),
+            Opcode::LoadIndirect(r, m) => {
+                let addr = self.base(r).wrapping_add(self.accum);
+                self.accum = self.fetch(addr, m);
+            }
+            Opcode::Load(ref s) => self.accum = self.source(s),
+            Opcode::Store(ref s) => match *s {
+                Source::Ram(r, m, a) => {
+                    let mut addr = self.base(r).wrapping_add(u16::from(a));
+                    if let AddressingMode::Indirect = m {
+                        addr = self.read(addr);
+                    }
+                    self.write(addr, self.accum);
+                }
+                _ => return self.fault(),
+            },
+            Opcode::Add(ref s)
+            | Opcode::Sub(ref s)
+            | Opcode::And(ref s)
+            | Opcode::Or(ref s)
+            | Opcode::Xor(ref s) => {
+                let rhs = self.source(s);
+                self.alu(&op, rhs);
+            }
+            Opcode::Shift(d, ref s) => {
+                let rhs = match *s {
+                    ShiftSource::Const(c) => u16::from(c),
+                    ShiftSource::Data => u16::from(self.input),
+                    ShiftSource::Ram(r, m, a) => self.fetch(self.base(r).wrapping_add(u16::from(a)), m),
+                };
+                // The decoder reads the direction from bit 0 only for a direct
+                // memory operand.  An indirect one sets bit 8, so it always
+                // shifts right.
+                let d = match *s {
+                    ShiftSource::Ram(_, AddressingMode::Indirect, _) => Direction::Right,
+                    _ => d,
+                };
+                self.alu(&Opcode::Shift(d, s.clone()), rhs);
+            }
+            Opcode::Branch(ref t) => self.pc = next.wrapping_add(t.extended()),
+            Opcode::Call(ref t) => self.call(next, t.extended()),
+        }
+        event
+    }
+
+    fn fault(&mut self) -> Step {
+        self.faulted = true;
+        Step::Fault
+    }
+
+    fn condition(&self, c: &Condition) -> bool {
+        match c {
+            Condition::Zero => !self.zero,
+            Condition::NotZero => self.zero,
+            Condition::Else => !self.skipped,
+            Condition::NotElse => self.skipped,
+            Condition::Negative => !self.neg,
+            Condition::NotNegative => self.neg,
+            Condition::Carry => !self.carry,
+            Condition::NotCarry => self.carry,
+        }
+    }
+
+    fn call(&mut self, ret: u16, addr: u16) {
+        self.sp = self.sp.wrapping_sub(2);
+        self.write(self.sp, ret);
+        self.pc = addr;
+    }
+
+    fn base(&self, r: RelativeTo) -> u16 {
+        match r {
+            RelativeTo::DataPointer => self.dp,
+            RelativeTo::StackPointer => self.sp,
+        }
+    }
+
+    fn fetch(&self, addr: u16, m: AddressingMode) -> u16 {
+        match m {
+            AddressingMode::Direct => self.read(addr),
+            AddressingMode::Indirect => self.read(self.read(addr)),
+        }
+    }
+
+    fn source(&self, s: &Source) -> u16 {
+        match *s {
+            Source::Const(ByteInWord::Lo, c) => u16::from(c),
+            Source::Const(ByteInWord::Hi, c) => u16::from(c) << 8,
+            Source::Data(ByteInWord::Lo) => u16::from(self.input),
+            Source::Data(ByteInWord::Hi) => u16::from(self.input) << 8,
+            Source::Ram(r, m, a) => self.fetch(self.base(r).wrapping_add(u16::from(a)), m),
+        }
+    }
+
+    fn alu(&mut self, op: &Opcode, rhs: u16) {
+        let a = self.accum;
+        let (result, carry) = match op {
+            Opcode::Add(_) => a.overflowing_add(rhs),
+            Opcode::Sub(_) => a.overflowing_sub(rhs),
+            Opcode::And(_) => (a & rhs, false),
+            Opcode::Or(_) => (a | rhs, false),
+            Opcode::Xor(_) => (a ^ rhs, false),
+            Opcode::Not => (!a, true),
+            Opcode::Test => (a, false),
+            Opcode::Shift(Direction::Left, _) => {
+                let result = a.checked_shl(u32::from(rhs)).unwrap_or(0);
+                let carry = (1..=16).contains(&rhs) && a & (1 << (16 - rhs)) != 0;
+                (result, carry)
+            }
+            Opcode::Shift(Direction::Right, _) => {
+                let result = a.checked_shr(u32::from(rhs)).unwrap_or(0);
+                let carry = (1..=16).contains(&rhs) && a & (1 << (rhs - 1)) != 0;
+                (result, carry)
+            }
+            _ => unreachable!(),
+        };
+        self.accum = result;
+        self.zero = result == 0;
+        self.neg = result & 0x8000 != 0;
+        self.carry = carry;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble::assemble;
+    use crate::parse::parse;
+
+    /// Runs `src` until it stops, or for at most a thousand instructions.
+    fn run(src: &str) -> (Machine, Step) {
+        let image = assemble(&parse(src).unwrap()).unwrap().image();
+        let mut machine = Machine::new(&image);
+        for _ in 0..1000 {
+            match machine.step() {
+                Step::Continue | Step::Output(_) => {}
+                step => return (machine, step),
+            }
+        }
+        panic!("did not stop");
+    }
+
+    #[test]
+    fn flags() {
+        let (m, _) = run("LOAD #0x01\nSUB #0x02\nHALT\n");
+        assert_eq!((m.accum, m.zero, m.neg, m.carry), (0xFFFF, false, true, true));
+        let (m, _) = run("LOAD #0x80\nSHL #9\nHALT\n");
+        assert_eq!((m.accum, m.zero, m.neg, m.carry), (0, true, false, true));
+        let (m, _) = run("NOT\nAND #0x0F\nHALT\n");
+        assert_eq!((m.accum, m.zero, m.neg, m.carry), (0x0F, false, false, false));
+        // Loads leave the flags alone.
+        let (m, _) = run("TEST\nLOAD #0x01\nHALT\n");
+        assert_eq!((m.accum, m.zero), (1, true));
+    }
+
+    #[test]
+    fn skips() {
+        // A skipped `LOADW` is passed over whole, and `STATUS` runs anyway,
+        // reporting the skip.
+        let (m, _) = run("LOAD #0x01\nTEST\nIF Z\nLOADW #0x1234\nSTATUS\nHALT\n");
+        assert_eq!(m.accum, 0x20);
+        let (m, _) = run("TEST\nIF Z\nLOADW #0x1234\nHALT\n");
+        assert_eq!(m.accum, 0x1234);
+        // `IF E` runs what follows only if the instruction before it was
+        // skipped, even a `NOP`.
+        let (m, _) = run("LOAD #0x01\nTEST\nIF Z\nNOP\nIF E\nLOAD #0x07\nHALT\n");
+        assert_eq!(m.accum, 7);
+        let (m, _) = run("TEST\nIF Z\nNOP\nIF E\nLOAD #0x07\nHALT\n");
+        assert_eq!(m.accum, 0);
+    }
+
+    #[test]
+    fn shifts() {
+        let setup = "LOAD #0x42\nSTORE [dp+0x40]\nLOAD #0x01\nSTORE [dp+0x42]\nLOAD #0x80\n";
+        let (m, _) = run(&format!("{setup}SHL [dp+0x42]\nHALT\n"));
+        assert_eq!(m.accum, 0x100);
+        let (m, _) = run(&format!("{setup}SHR [dp+0x42]\nHALT\n"));
+        assert_eq!(m.accum, 0x40);
+        // Through a pointer the hardware shifts right, whatever the encoded
+        // direction.
+        let (m, _) = run(&format!("{setup}SHL [[dp+0x40]]\nHALT\n"));
+        assert_eq!(m.accum, 0x40);
+    }
+
+    #[test]
+    fn calls() {
+        let (m, step) = run("CALL sub\nHALT\nsub:\nPUSH\nLOAD #0x09\nDROP\nRET\n");
+        assert_eq!((step, m.accum, m.sp), (Step::Halt, 9, 0));
+        // The call pushed its return address, and `PUSH` the accumulator
+        // below it.
+        assert_eq!((m.read(0xFFFE), m.read(0xFFFC)), (2, 0));
+    }
+
+    #[test]
+    fn faults() {
+        let (m, step) = run(".allow fault\nSTORE #0x00\nHALT\n");
+        assert_eq!((step, m.faulted), (Step::Fault, true));
+        let (_, step) = run(".byte 0x11\n");
+        assert_eq!(step, Step::Fault);
+    }
+}
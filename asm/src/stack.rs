@@ -0,0 +1,243 @@
+//! How deep the stack can get.  SP starts at 0, so the first `Push` writes
+//! 0xFFFE and the stack grows down toward the code.
+//!
+//! Every subroutine is walked from its entry, counting the bytes it has
+//! pushed: `Push` adds two, `Pop` and `Drop` take two away, and a call adds
+//! two for the return address on top of whatever the callee needs.  A
+//! block reached with different counts along different paths, or a
+//! `Return` with anything but the return address on top, is a problem.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::cfg::{Cfg, EdgeKind};
+use crate::Opcode;
+
+/// A subroutine, or the code started at reset.
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub entry: u16,
+    /// The most bytes it pushes at once, not counting calls.
+    pub frame: usize,
+    /// The entries of the subroutines it calls.
+    pub calls: Vec<u16>,
+    /// Whether it also calls through `CALL a`, which the analysis cannot
+    /// follow.
+    pub unresolved: bool,
+    /// Whether it may call itself, directly or not.
+    pub recursive: bool,
+    /// The most stack it needs, including its callees, or `None` if that
+    /// has no bound because of recursion.
+    pub worst: Option<usize>,
+}
+
+/// Something wrong with the stack at `addr`.
+#[derive(Debug, Clone)]
+pub struct Problem {
+    pub addr: u16,
+    pub message: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Analysis {
+    /// Every function, in address order, starting with the entry point's.
+    pub functions: Vec<Function>,
+    pub problems: Vec<Problem>,
+}
+
+impl Analysis {
+    /// The most stack the program needs, or `None` if it is unbounded.
+    pub fn worst(&self) -> Option<usize> {
+        self.functions.first().and_then(|f| f.worst)
+    }
+}
+
+/// Analyzes the graph of a program started at `entry`.
+pub fn analyze(cfg: &Cfg, entry: u16) -> Analysis {
+    let mut entries = vec![entry];
+    for block in &cfg.blocks {
+        for edge in block.edges.iter().filter(|edge| edge.kind == EdgeKind::Call) {
+            if !entries.contains(&edge.to) {
+                entries.push(edge.to);
+            }
+        }
+    }
+    entries[1..].sort();
+
+    let mut problems = vec![];
+    let (mut functions, sites): (Vec<_>, Vec<_>) = entries.iter().map(|&entry| walk(cfg, entry, &mut problems)).unzip();
+
+    // A function needs its own frame, or what any of its calls needs on
+    // top of what it has pushed by then, whichever is more.
+    let calls: CallGraph = functions
+        .iter()
+        .zip(&sites)
+        .map(|(function, sites)| (function.entry, (function.frame, sites.as_slice())))
+        .collect();
+    let mut memo = HashMap::new();
+    for function in &mut functions {
+        function.worst = needs(function.entry, &calls, &mut memo, &mut vec![]);
+        function.recursive = calls_itself(function.entry, &calls);
+    }
+    problems.sort_by_key(|problem| problem.addr);
+    Analysis { functions, problems }
+}
+
+/// The bytes a function has pushed at each of its calls, with the callee.
+type Sites = Vec<(usize, u16)>;
+
+/// Each function's frame and calls, by entry.
+type CallGraph<'a> = HashMap<u16, (usize, &'a [(usize, u16)])>;
+
+/// Follows one function from `entry`, without going into the functions it
+/// calls.
+fn walk(cfg: &Cfg, entry: u16, problems: &mut Vec<Problem>) -> (Function, Sites) {
+    let blocks = cfg.blocks.iter().map(|block| (block.start, block)).collect::<HashMap<_, _>>();
+    let mut function = Function { entry, frame: 0, calls: vec![], unresolved: false, recursive: false, worst: None };
+    let mut sites = vec![];
+    let mut depths = BTreeMap::from([(entry, 0)]);
+    let mut work = vec![entry];
+    let mut problem = |addr: u16, message: String| {
+        let problem = Problem { addr, message };
+        if !problems.iter().any(|p| p.addr == problem.addr && p.message == problem.message) {
+            problems.push(problem);
+        }
+    };
+
+    while let Some(start) = work.pop() {
+        let Some(block) = blocks.get(&start) else { continue };
+        let mut depth: i64 = depths[&start];
+        for line in &block.lines {
+            let addr = line.addr as u16;
+            match line.op {
+                Opcode::Push => depth += 2,
+                Opcode::Pop | Opcode::Drop => {
+                    depth -= 2;
+                    if depth < 0 {
+                        problem(addr, format!("`{}` pops more than was pushed", line.op));
+                    }
+                }
+                Opcode::Return if depth > 0 => problem(
+                    addr,
+                    format!("`RET` returns to the last value pushed, not the caller: {depth} bytes are still on the stack"),
+                ),
+                Opcode::Call(_) | Opcode::CallWord(_) | Opcode::CallIndirect => {
+                    if let Some(edge) = block.edges.iter().find(|edge| edge.kind == EdgeKind::Call) {
+                        sites.push((depth.max(0) as usize, edge.to));
+                        if !function.calls.contains(&edge.to) {
+                            function.calls.push(edge.to);
+                        }
+                    } else {
+                        function.unresolved = true;
+                    }
+                }
+                _ => {}
+            }
+            function.frame = function.frame.max(depth.max(0) as usize);
+        }
+        for edge in block.edges.iter().filter(|edge| edge.kind != EdgeKind::Call) {
+            match depths.get(&edge.to) {
+                None => {
+                    depths.insert(edge.to, depth);
+                    work.push(edge.to);
+                }
+                Some(&other) if other != depth => {
+                    let (a, b) = (other.min(depth), other.max(depth));
+                    problem(edge.to, format!("reached with {a} bytes pushed on one path and {b} on another"));
+                }
+                Some(_) => {}
+            }
+        }
+    }
+    function.calls.sort();
+    (function, sites)
+}
+
+/// The most stack `entry` needs, or `None` if it may call itself, directly
+/// or not.  `path` holds the functions being worked out.
+fn needs(
+    entry: u16,
+    calls: &CallGraph,
+    memo: &mut HashMap<u16, Option<usize>>,
+    path: &mut Vec<u16>,
+) -> Option<usize> {
+    if let Some(worst) = memo.get(&entry) {
+        return *worst;
+    }
+    if path.contains(&entry) {
+        return None;
+    }
+    let (frame, sites) = calls.get(&entry)?;
+    path.push(entry);
+    let mut worst = Some(*frame);
+    for (depth, callee) in sites.iter() {
+        worst = match (worst, needs(*callee, calls, memo, path)) {
+            (Some(worst), Some(callee)) => Some(worst.max(depth + 2 + callee)),
+            _ => None,
+        };
+    }
+    path.pop();
+    memo.insert(entry, worst);
+    worst
+}
+
+/// Whether `entry` can reach itself through its calls.
+fn calls_itself(entry: u16, calls: &CallGraph) -> bool {
+    let mut seen = vec![];
+    let mut work = vec![entry];
+    while let Some(function) = work.pop() {
+        for (_, callee) in calls.get(&function).map_or(&[][..], |(_, sites)| sites) {
+            if *callee == entry {
+                return true;
+            }
+            if !seen.contains(callee) {
+                seen.push(*callee);
+                work.push(*callee);
+            }
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble::assemble;
+    use crate::parse::parse;
+
+    fn analyze_src(src: &str) -> Analysis {
+        let image = assemble(&parse(src).unwrap()).unwrap().image();
+        analyze(&Cfg::recover(&image, 0), 0)
+    }
+
+    fn problems(src: &str) -> Vec<String> {
+        analyze_src(src).problems.into_iter().map(|problem| problem.message).collect()
+    }
+
+    #[test]
+    fn imbalance() {
+        assert_eq!(problems("TEST\nIF Z\nPUSH\nHALT\n"), ["reached with 0 bytes pushed on one path and 2 on another"]);
+        assert_eq!(problems("POP\nHALT\n"), ["`POP` pops more than was pushed"]);
+        assert_eq!(
+            problems("CALL sub\nHALT\nsub:\nPUSH\nRET\n"),
+            ["`RET` returns to the last value pushed, not the caller: 2 bytes are still on the stack"]
+        );
+        assert_eq!(problems("PUSH\nCALL sub\nDROP\nHALT\nsub:\nRET\n"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn depth() {
+        let analysis = analyze_src("PUSH\nCALL sub\nDROP\nHALT\nsub:\nPUSH\nPUSH\nDROP\nDROP\nRET\n");
+        let frames = analysis.functions.iter().map(|f| (f.frame, f.worst, f.recursive)).collect::<Vec<_>>();
+        assert_eq!(frames, [(2, Some(8), false), (4, Some(4), false)]);
+        assert_eq!(analysis.worst(), Some(8));
+    }
+
+    #[test]
+    fn recursion() {
+        let analysis = analyze_src("CALL sub\nHALT\nsub:\nTEST\nIF Z\nRET\nCALL sub\nRET\n");
+        assert!(analysis.functions[1].recursive);
+        assert_eq!(analysis.functions[1].worst, None);
+        assert_eq!(analysis.worst(), None);
+        assert!(analysis.problems.is_empty());
+    }
+}
@@ -0,0 +1,3 @@
+    ; The CPU faults on a store to a constant.
+    .allow fault
+    STORE #0x00
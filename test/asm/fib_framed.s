@@ -0,0 +1,61 @@
+    ; the frame, addressed relative to dp
+    .equ frame, 0x60
+    .equ target, 0x00
+    .equ current, 0x02
+    .equ cursor, 0x04
+    .equ pointer, 0x06
+    .equ cache, 0x08
+
+    LOAD in
+    STORE [dp+frame]
+    LOAD #frame
+    SETDP
+    NOP
+    LOAD #0x01
+    STORE [dp+cache]
+    LOAD #0x01
+    STORE [dp+cache+2]
+    LOAD [dp+target]
+    TEST
+    IF Z
+    BR done
+    SUB #0x01
+    IF Z
+    BR done
+    LOAD #0x02
+    STORE [dp+current]
+loop:
+    LOAD [dp+current]
+    ADD [dp+current]
+    ADD #cache
+    STORE [dp+cursor]
+    ADD #frame
+    STORE [dp+pointer]
+    LOAD [dp+cursor]
+    SUB #0x02
+    LOAD [dp+a]
+    STORE [[dp+pointer]]
+    LOAD [dp+cursor]
+    SUB #0x04
+    LOAD [dp+a]
+    ADD [[dp+pointer]]
+    STORE [[dp+pointer]]
+    OUTLO
+    NOP
+    LOAD [dp+target]
+    SUB [dp+current]
+    IF Z
+    BR done
+    LOAD [dp+current]
+    ADD #0x01
+    STORE [dp+current]
+    BR loop
+done:
+    LOAD [dp+target]
+    ADD [dp+target]
+    ADD #cache
+    LOAD [dp+a]
+    OUTLO
+    HALT
+    .org frame
+    .space 0x18
@@ -0,0 +1,80 @@
+    LOAD in
+    STORE [dp+0x50]
+    LOAD #0x01
+    STORE [dp+0x58]
+    LOAD #0x01
+    STORE [dp+0x5A]
+    LOAD [dp+0x50]
+    TEST
+    IF Z
+    BR done
+    SUB #0x01
+    IF Z
+    BR done
+    LOAD #0x02
+    STORE [dp+0x52]
+loop:
+    LOAD [dp+0x52]
+    ADD [dp+0x52]
+    ADD #0x58
+    STORE [dp+0x54]
+    SUB #0x02
+    LOAD [dp+a]
+    STORE [[dp+0x54]]
+    LOAD [dp+0x54]
+    SUB #0x04
+    LOAD [dp+a]
+    ADD [[dp+0x54]]
+    STORE [[dp+0x54]]
+    OUTLO
+    NOP
+    LOAD [dp+0x50]
+    SUB [dp+0x52]
+    IF Z
+    BR done
+    LOAD [dp+0x52]
+    ADD #0x01
+    STORE [dp+0x52]
+    BR loop
+done:
+    LOAD [dp+0x50]
+    ADD [dp+0x50]
+    ADD #0x58
+    LOAD [dp+a]
+    OUTLO
+    HALT
+    ; target
+    NOP
+    NOP
+    ; current
+    NOP
+    NOP
+    ; cursor
+    NOP
+    NOP
+    ; cache
+    NOP
+    NOP
+    NOP
+    NOP
+    NOP
+    NOP
+    NOP
+    NOP
+    NOP
+    NOP
+    NOP
+    NOP
+    NOP
+    NOP
+    NOP
+    NOP
+    NOP
+    NOP
+    NOP
+    NOP
+    NOP
+    NOP
+    NOP
+    NOP
+    NOP
@@ -0,0 +1,6 @@
+    LOAD in
+    PUSH
+    NOP
+    CALL fib_fn
+    OUTLO
+    HALT
@@ -0,0 +1,28 @@
+    ; fib_fn(n): the nth Fibonacci number, for n passed on the stack
+    .global fib_fn
+fib_fn:
+    .frame n
+    LOAD [sp+n]
+    TEST
+    IF Z
+    BR just_one
+    SUB #0x01
+    IF Z
+    BR just_one
+    PUSH
+    CALL fib_fn
+    DROP
+    PUSH
+    .local prev
+    LOAD [sp+n]
+    SUB #0x02
+    PUSH
+    CALL fib_fn
+    DROP
+    ADD [sp+prev]
+    DROP
+    RET
+just_one:
+    LOAD #0x01
+    RET
+    NOP
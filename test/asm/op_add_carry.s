@@ -0,0 +1,35 @@
+.macro assert_carry
+    STATUS
+    AND #0x04
+    IF Z
+    TRAP
+.endm
+
+.macro assert_no_carry
+    STATUS
+    AND #0x04
+    IF NZ
+    TRAP
+.endm
+
+    ; FFFE + 1 => no carry
+    LOADW #0xFFFE
+    ADD #0x01
+    assert_no_carry
+    ; FFFF + 1 => carry
+    LOADW #0xFFFF
+    ADD #0x01
+    assert_carry
+    ; 1 + FFFF => carry
+    LOADW #0xFFFF
+    STORE [dp+0xF0]
+    LOAD #0x01
+    ADD [dp+0xF0]
+    assert_carry
+    ; FFFF + FF00 => carry
+    LOADW #0xFFFE
+    ADD #0xFF00
+    assert_carry
+    ; done
+    LOAD #0x01
+    OUTLO
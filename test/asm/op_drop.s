@@ -0,0 +1,36 @@
+    LOAD #0x05
+    PUSH
+    LOAD #0x04
+    PUSH
+    LOAD #0x03
+    PUSH
+    LOAD #0x02
+    PUSH
+    LOAD #0x01
+    PUSH
+    LOAD #0x09
+    TEST
+    IF Z
+    DROP
+    LOAD #0x08
+    TEST
+    IF Z
+    NOP
+    IF NE
+    DROP
+    LOAD #0x07
+    TEST
+    IF NZ
+    NOP
+    IF E
+    DROP
+    POP
+    OUTLO
+    DROP
+    OUTLO
+    POP
+    OUTLO
+    DROP
+    OUTLO
+    POP
+    OUTLO
@@ -0,0 +1,19 @@
+    LOAD #0x00
+    TEST
+    IF NZ
+    HALT
+    LOAD #0x00
+    TEST
+    IF NZ
+    NOP
+    IF NE
+    HALT
+    LOAD #0x00
+    TEST
+    IF Z
+    NOP
+    IF E
+    HALT
+    LOAD #0x01
+    OUTLO
+    HALT
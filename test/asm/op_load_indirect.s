@@ -0,0 +1,39 @@
+    BR start            ; skip over these
+    .byte 0x00
+    .byte 0x04          ; pointer to V
+    .byte 0x00
+    .byte 0x42
+start:
+    LOAD #0x04          ; pointer to second ^
+    PUSH
+    ; data direct
+    LOAD #0x02          ; pointer to first ^
+    LOAD [dp+a]
+    ; assert eq 0x04
+    SUB #0x04
+    IF NZ
+    TRAP
+    ; data indirect
+    LOAD #0x02          ; pointer to first ^
+    LOAD [[dp+a]]
+    ; assert eq 0x42
+    SUB #0x42
+    IF NZ
+    TRAP
+    ; stack direct
+    LOAD #0x00          ; top of stack
+    LOAD [sp+a]
+    ; assert eq 0x04
+    SUB #0x04
+    IF NZ
+    TRAP
+    ; stack indirect
+    LOAD #0x00          ; top of stack
+    LOAD [[sp+a]]
+    ; assert eq 0x42
+    SUB #0x42
+    IF NZ
+    TRAP
+    ; done
+    LOAD #0x01
+    OUTLO
@@ -0,0 +1,17 @@
+.macro assert_carry
+    STATUS
+    AND #0x04
+    IF Z
+    TRAP
+.endm
+
+    LOAD #0x00
+    NOT
+    assert_carry
+    ; value does not matter
+    LOADW #0xFFFF
+    NOT
+    assert_carry
+    ; done
+    LOAD #0x01
+    OUTLO
@@ -0,0 +1,35 @@
+    LOAD #0x05
+    PUSH
+    LOAD #0x04
+    PUSH
+    LOAD #0x03
+    PUSH
+    LOAD #0x02
+    PUSH
+    LOAD #0x01
+    PUSH
+    LOAD #0x09
+    IF Z
+    POP
+    LOAD #0x08
+    TEST
+    IF Z
+    NOP
+    IF NE
+    POP
+    LOAD #0x07
+    TEST
+    IF NZ
+    NOP
+    IF E
+    POP
+    POP
+    OUTLO
+    POP
+    OUTLO
+    POP
+    OUTLO
+    POP
+    OUTLO
+    POP
+    OUTLO
@@ -0,0 +1,27 @@
+    LOAD #0x42
+    PUSH
+    LOAD #0x01
+    TEST
+    IF Z
+    PUSH
+    LOAD #0x02
+    TEST
+    IF Z
+    NOP
+    IF NE
+    PUSH
+    LOAD #0x03
+    TEST
+    IF NZ
+    NOP
+    IF E
+    PUSH
+    LOAD #0x04
+    TEST
+    IF NZ
+    PUSH
+    LOAD #0x00
+    POP
+    OUTLO
+    POP
+    OUTLO
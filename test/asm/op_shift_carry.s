@@ -0,0 +1,18 @@
+.macro assert_carry
+    STATUS
+    AND #0x04
+    IF Z
+    TRAP
+.endm
+
+    ; 1 >> 1 => carry
+    LOAD #0x01
+    SHR #1
+    assert_carry
+    ; 0x8000 << 1 => carry
+    LOADW #0x8000
+    SHL #1
+    assert_carry
+    ; done
+    LOAD #0x01
+    OUTLO
@@ -0,0 +1,32 @@
+    LOAD #0x00
+    TEST
+    STATUS
+    SUB #0x01
+    IF NZ
+    TRAP
+    LOAD #0x01
+    TEST
+    STATUS
+    SUB #0x00
+    IF NZ
+    TRAP
+    LOADW #0xFFFF
+    TEST
+    STATUS
+    SUB #0x02
+    IF NZ
+    TRAP
+    STATUS
+    SUB #0x21
+    IF NZ
+    TRAP
+    LOAD #0x00
+    TEST
+    IF Z
+    NOP
+    STATUS
+    SUB #0x01
+    IF NZ
+    TRAP
+    LOAD #0x01
+    OUTLO
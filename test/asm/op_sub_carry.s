@@ -0,0 +1,37 @@
+.macro assert_carry
+    STATUS
+    AND #0x04
+    IF Z
+    TRAP
+.endm
+
+.macro assert_no_carry
+    STATUS
+    AND #0x04
+    IF NZ
+    TRAP
+.endm
+
+    ; 1 - 1 => no carry
+    LOAD #0x01
+    SUB #0x01
+    assert_no_carry
+    ; 0 - 1 => carry
+    LOAD #0x00
+    SUB #0x01
+    assert_carry
+    ; 7FFF - 8000 => carry
+    LOADW #0x8000
+    STORE [dp+0xF0]
+    LOADW #0x7FFF
+    SUB [dp+0xF0]
+    assert_carry
+    ; 8000 - 8001 => carry
+    LOADW #0x8001
+    STORE [dp+0xF0]
+    LOADW #0x8000
+    SUB [dp+0xF0]
+    assert_carry
+    ; done
+    LOAD #0x01
+    OUTLO
@@ -0,0 +1,18 @@
+    LOAD #0xFF
+    OUTLO
+    LOADW #0x0000
+    TEST
+    IF Z
+    OUTLO
+    LOADW #0x0001
+    TEST
+    IF Z
+    OUTLO
+    LOADW #0x0001
+    TEST
+    IF NZ
+    OUTLO
+    LOADW #0x0000
+    TEST
+    IF NZ
+    OUTLO
@@ -0,0 +1,21 @@
+    LOAD #0x00
+    TEST
+    IF NZ
+    TRAP
+    LOAD #0x00
+    TEST
+    IF NZ
+    NOP
+    IF NE
+    TRAP
+    LOAD #0x00
+    TEST
+    IF Z
+    NOP
+    IF E
+    TRAP
+    LOAD #0x01
+    OUTLO
+    TRAP
+    LOAD #0x02
+    OUTLO
@@ -0,0 +1,187 @@
+    NOP
+    NOP
+    NOP
+    NOP
+    LOAD #0x14
+    ADD #0x1E
+    OUTLO
+    NOP
+    LOAD in
+    ADD #0x1E
+    OUTLO
+    NOP
+    LOAD [dp+operands]
+    ADD [dp+operands+2]
+    OUTLO
+    NOP
+    BR forward
+    .org 0x20
+operands:
+    .word 0x0014, 0x001E
+backward:
+    LOAD #0x5A
+    BR after
+forward:
+    LOAD #0xA5
+    OUTLO
+    NOP
+    LOAD #0x00
+    BR backward
+after:
+    OUTLO
+    NOP
+    LOAD in
+    TEST
+    IF NZ
+    BR after
+    LOAD #0x09
+    STORE [dp+operands]
+    LOAD #0x33
+    LOAD [dp+operands]
+    ADD [dp+operands+2]
+    OUTLO
+    NOP
+    NOP
+    NOP
+    NOP
+    LOAD #0xFF
+    SUB #0xEE
+    OUTLO
+    NOP
+    NOP
+    NOP
+    LOAD #0xF0
+    AND #0x3C
+    OUTLO
+    NOP
+    NOP
+    NOP
+    LOAD #0xF0
+    OR #0x3C
+    OUTLO
+    NOP
+    NOP
+    NOP
+    LOAD #0xF0
+    XOR #0x3C
+    OUTLO
+    NOP
+    NOP
+    NOP
+    LOAD #0xA5
+    NOT
+    OUTLO
+    LOAD #operands
+    LOAD [dp+a]
+    OUTLO
+    LOAD #operands+2
+    STORE [dp+0x1E]
+    LOAD #0x00
+    LOAD [[dp+0x1E]]
+    OUTLO
+    NOP
+    NOP
+    NOP
+    LOAD #0x99
+    PUSH
+    NOP
+    LOAD #0x00
+    LOAD [sp]
+    OUTLO
+    NOP
+    LOAD #0x00
+    POP
+    NOP
+    ADD #0x11
+    OUTLO
+    NOP
+    NOP
+    NOP
+    CALL callee
+    OUTLO
+    NOP
+    NOP
+    NOP
+    BR after_callee
+callee:
+    LOAD #0x42
+    RET
+    NOP
+after_callee:
+    LOAD #0x22
+    OR #0x3300
+    OUTLO
+    OUTHI
+    NOP
+    NOP
+    TRAP
+    NOP
+    LOAD #0x00
+    TEST
+    IF NZ
+    LOAD #0xBD
+    IF E
+    LOAD #0xA4
+    OUTLO
+    LOAD #0x00
+    TEST
+    IF Z
+    LOAD #0xBD
+    IF NE
+    LOAD #0xA5
+    OUTLO
+    LOAD #0x02
+    STORE [dp+0x1E]
+    LOAD #0x01
+    OUTLO
+    SHL #1
+    OUTLO
+    SHL #1
+    OUTLO
+    SHL #1
+    OUTLO
+    SHL #1
+    OUTLO
+    SHL #1
+    OUTLO
+    SHL #1
+    OUTLO
+    SHL #1
+    OUTLO
+    SHR [dp+0x1E]
+    OUTLO
+    SHR [dp+0x1E]
+    OUTLO
+    SHR [dp+0x1E]
+    OUTLO
+    SHR #1
+    OUTLO
+    SHR #1
+    OUTLO
+    NOP
+    NOP
+    LOAD #0x02
+    BR a
+    LOAD #0x03
+    OUTLO
+    LOAD #callee
+    CALL a
+    OUTLO
+    NOP
+    NOP
+    NOP
+    LOADW #0xABCD
+    OUTHI
+    OUTLO
+    CALLW callee
+    OUTLO
+    LOADW #0xFFFF
+    TEST
+    IF N
+    LOAD #0x72
+    OUTLO
+    LOADW #0xFFFF
+    TEST
+    IF NN
+    LOAD #0x72
+    OUTLO